@@ -0,0 +1,85 @@
+//! Benchmarks of the parser hot path, run with "cargo bench"
+//!
+//! The crate is a binary only, so the parser is compiled into the benchmark along with the
+//! modules it depends on. Only part of them is used, and their tests aren't compiled in, which
+//! leaves their test imports unused.
+
+use std::hint::black_box;
+
+use chrono::NaiveDateTime;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/parser.rs"]
+mod parser;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/signals.rs"]
+mod signals;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/tail.rs"]
+mod tail;
+
+use parser::{parse_datetime, parse_str, LineFormat, MessageDelimiter, NormalLogLineRef};
+
+const LINE: &str = "<INFO> 02-Jan-2024::15:04:05.123 ncs-python-vm-l3vpn Thread-12: - Service \
+    l3vpn created, device=pe1 vrf=customer-a";
+
+/// A synthetic log of single-line messages with a multi-line traceback now and then
+fn synthetic_log(messages: usize) -> String {
+    let mut log = String::new();
+    for i in 0..messages {
+        log.push_str(&format!(
+            "<{}> 02-Jan-2024::15:{:02}:{:02}.{:03} ncs-python-vm-l3vpn Thread-{}: - Message {}\n",
+            ["DEBUG", "INFO", "WARNING", "ERROR"][i % 4],
+            i / 60 % 60,
+            i % 60,
+            i % 1000,
+            i % 16,
+            i
+        ));
+        if i % 50 == 0 {
+            log.push_str("Traceback (most recent call last):\n  File \"main.py\", line 1\n");
+        }
+    }
+    log
+}
+
+fn datetime(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_datetime");
+    group.bench_function("parse_datetime", |b| {
+        b.iter(|| parse_datetime(black_box("02-Jan-2024::15:04:05.123")))
+    });
+    // What parse_datetime does for timestamps it can't parse by hand
+    group.bench_function("chrono", |b| {
+        b.iter(|| {
+            NaiveDateTime::parse_from_str(
+                black_box("02-Jan-2024::15:04:05.123"),
+                "%d-%b-%Y::%H:%M:%S%.3f",
+            )
+        })
+    });
+    group.finish();
+}
+
+fn line(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_line");
+    group.throughput(Throughput::Bytes(LINE.len() as u64));
+    group.bench_function("parse_line", |b| {
+        b.iter(|| NormalLogLineRef::parse(black_box(LINE), MessageDelimiter::Dash))
+    });
+    group.finish();
+}
+
+fn log(c: &mut Criterion) {
+    let log = synthetic_log(10_000);
+
+    let mut group = c.benchmark_group("LogParser");
+    group.throughput(Throughput::Bytes(log.len() as u64));
+    group.bench_function("LogParser", |b| {
+        b.iter(|| parse_str(black_box(&log), LineFormat::Nso(MessageDelimiter::Dash)).count())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, datetime, line, log);
+criterion_main!(benches);
@@ -0,0 +1,146 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Splits a line into escape sequences and the visible text between them
+///
+/// Only CSI sequences ("\x1b[...") are recognized, which covers colors. Other escapes are dropped
+/// along with the character following them.
+///
+fn segments(line: &str) -> impl Iterator<Item = (&str, bool)> {
+    let mut rest = line;
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let end = match rest.strip_prefix('\x1b') {
+            Some(escape) => {
+                let mut chars = escape.char_indices();
+                let len = match chars.next() {
+                    // Skip until the final byte of the escape sequence
+                    Some((_, '[')) => chars
+                        .find(|(_, c)| ('\x40'..='\x7e').contains(c))
+                        .map_or(escape.len(), |(i, c)| i + c.len_utf8()),
+                    Some((_, c)) => c.len_utf8(),
+                    None => 0,
+                };
+                let (segment, remaining) = rest.split_at(1 + len);
+                rest = remaining;
+                return Some((segment, true));
+            }
+            None => rest.find('\x1b').unwrap_or(rest.len()),
+        };
+
+        let (segment, remaining) = rest.split_at(end);
+        rest = remaining;
+        Some((segment, false))
+    })
+}
+
+/// Removes ANSI escape sequences, such as colors
+pub fn strip_ansi(line: &str) -> String {
+    segments(line)
+        .filter(|(_, escape)| !escape)
+        .map(|(text, _)| text)
+        .collect()
+}
+
+/// The number of columns a line takes up in a terminal, ignoring escape sequences and counting
+/// wide (e.g. CJK) characters as two
+pub fn display_width(line: &str) -> usize {
+    segments(line)
+        .filter(|(_, escape)| !escape)
+        .map(|(text, _)| text.width())
+        .sum()
+}
+
+/// Breaks a line into lines at most `width` columns wide
+///
+/// Escape sequences are kept intact and wide characters are never split across lines.
+///
+pub fn hard_wrap(line: &str, width: usize) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut column = 0;
+
+    for (segment, escape) in segments(line) {
+        if escape {
+            result.push_str(segment);
+            continue;
+        }
+
+        for c in segment.chars() {
+            if c == '\n' {
+                result.push(c);
+                column = 0;
+                continue;
+            }
+
+            let char_width = c.width().unwrap_or(0);
+
+            if column > 0 && column + char_width > width {
+                result.push('\n');
+                column = 0;
+            }
+
+            result.push(c);
+            column += char_width;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn width_ignores_escapes() {
+        assert_eq!(display_width("plain"), 5);
+        assert_eq!(display_width("\x1b[31mred\x1b[0m text"), 8);
+        assert_eq!(display_width("\x1b[1;38;5;208mbold orange\x1b[39m"), 11);
+    }
+
+    #[test]
+    fn width_of_wide_characters() {
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("\x1b[32m日本\x1b[0mok"), 6);
+    }
+
+    #[test]
+    fn strips_escapes() {
+        assert_eq!(
+            strip_ansi("\x1b[31mred\x1b[0m and \x1b[1mbold\x1b[22m"),
+            "red and bold"
+        );
+        // The escape is dropped along with the character after it
+        assert_eq!(strip_ansi("a\x1bMb"), "ab");
+        // An unterminated escape is dropped until the end
+        assert_eq!(strip_ansi("a\x1b[31"), "a");
+    }
+
+    #[test]
+    fn wraps() {
+        assert_eq!(hard_wrap("abcdefgh", 3), "abc\ndef\ngh");
+        assert_eq!(hard_wrap("ab\ncdef", 3), "ab\ncde\nf");
+        assert_eq!(hard_wrap("abc", 10), "abc");
+    }
+
+    #[test]
+    fn wrapping_keeps_escapes_intact() {
+        let wrapped = hard_wrap("\x1b[31mabcdef\x1b[0mgh", 4);
+
+        assert_eq!(wrapped, "\x1b[31mabcd\nef\x1b[0mgh");
+        for line in wrapped.lines() {
+            assert!(display_width(line) <= 4);
+        }
+    }
+
+    #[test]
+    fn wrapping_never_splits_wide_characters() {
+        assert_eq!(hard_wrap("日本語です", 5), "日本\n語で\nす");
+        assert_eq!(hard_wrap("a日本", 2), "a\n日\n本");
+        // Too wide for the width, but it has to go somewhere
+        assert_eq!(hard_wrap("日本", 1), "日\n本");
+    }
+}
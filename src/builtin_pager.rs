@@ -0,0 +1,271 @@
+use std::io::{stdout, Stdout, Write};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{read, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, size, Clear, ClearType, DisableLineWrap, EnableLineWrap,
+    EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::{execute, queue};
+
+use regex::Regex;
+
+use crate::ansi::strip_ansi;
+
+/// A minimal pager, used when "less" isn't available
+///
+/// Everything written to the pager is buffered, and the pager is shown once it's dropped. It
+/// supports scrolling with the arrow keys, j/k, space/b, g/G, searching forward with "/" and "n",
+/// and quitting with "q".
+///
+pub struct BuiltinPager {
+    title: String,
+    buffer: Vec<u8>,
+    start_at_top: bool,
+    jump_to: Option<Regex>,
+}
+
+impl BuiltinPager {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            buffer: Vec::new(),
+            start_at_top: false,
+            jump_to: None,
+        }
+    }
+
+    /// Start at the top rather than at the end, like "less" without "+G"
+    pub fn start_at_top(mut self, start_at_top: bool) -> Self {
+        self.start_at_top = start_at_top;
+        self
+    }
+
+    /// Start at the first line matching this regex, like "less +/PATTERN"
+    pub fn jump_to(mut self, jump_to: Option<Regex>) -> Self {
+        self.jump_to = jump_to;
+        self
+    }
+}
+
+impl Write for BuiltinPager {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BuiltinPager {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.buffer);
+        let lines: Vec<&str> = text.lines().collect();
+
+        // Like less, just pass everything through when not writing to a terminal
+        if !atty::is(atty::Stream::Stdout) {
+            let mut stdout = stdout();
+            for line in lines {
+                if writeln!(stdout, "{}", line).is_err() {
+                    return;
+                }
+            }
+            return;
+        }
+
+        if let Err(error) = run(
+            &self.title,
+            &lines,
+            self.start_at_top,
+            self.jump_to.as_ref(),
+        ) {
+            eprintln!("Pager error: {}", error);
+        }
+    }
+}
+
+fn run(
+    title: &str,
+    lines: &[&str],
+    start_at_top: bool,
+    jump_to: Option<&Regex>,
+) -> std::io::Result<()> {
+    let mut stdout = stdout();
+
+    let _terminal = TerminalGuard::enter(&mut stdout)?;
+
+    event_loop(&mut stdout, title, lines, start_at_top, jump_to)
+}
+
+/// Puts the terminal in raw mode on the alternate screen, and restores it when dropped
+///
+/// Dropping restores the terminal however the pager exits, including when setting it up fails
+/// halfway or the event loop fails or panics.
+///
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter(stdout: &mut Stdout) -> std::io::Result<Self> {
+        enable_raw_mode()?;
+        let guard = TerminalGuard;
+        execute!(stdout, EnterAlternateScreen, DisableLineWrap, Hide)?;
+
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        // There's nothing to be done if restoring fails, but it's still worth trying each step
+        let _ = execute!(stdout(), Show, EnableLineWrap, LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+fn event_loop(
+    stdout: &mut Stdout,
+    title: &str,
+    lines: &[&str],
+    start_at_top: bool,
+    jump_to: Option<&Regex>,
+) -> std::io::Result<()> {
+    // Searching is done on the text without colors
+    let plain_lines: Vec<String> = lines.iter().map(|line| strip_ansi(line)).collect();
+    let mut search: Option<String> = None;
+    let mut message: Option<String> = None;
+
+    // Start at the end by default, like "less +G"
+    let mut top = match (jump_to, start_at_top) {
+        (Some(regex), _) => match plain_lines.iter().position(|line| regex.is_match(line)) {
+            Some(i) => i,
+            None => {
+                message = Some("Pattern not found".to_string());
+                usize::MAX
+            }
+        },
+        (None, true) => 0,
+        (None, false) => usize::MAX,
+    };
+
+    loop {
+        let (_, rows) = size()?;
+        let page = (rows as usize).saturating_sub(1).max(1);
+        top = top.min(lines.len().saturating_sub(page));
+
+        let status = message.take().unwrap_or_else(|| {
+            let bottom = (top + page).min(lines.len());
+            let end = if bottom == lines.len() { " (END)" } else { "" };
+
+            format!(
+                "Reading log: {} [lines {}-{}/{}]{}",
+                title,
+                top + 1,
+                bottom,
+                lines.len(),
+                end
+            )
+        });
+
+        draw(
+            stdout,
+            &lines[top..(top + page).min(lines.len())],
+            rows,
+            &status,
+        )?;
+
+        let Event::Key(key) = read()? else {
+            continue;
+        };
+
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+            // Raw mode swallows SIGINT, so handle Ctrl+C ourselves
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+            KeyCode::Down | KeyCode::Char('j') | KeyCode::Enter => top += 1,
+            KeyCode::Up | KeyCode::Char('k') => top = top.saturating_sub(1),
+            KeyCode::PageDown | KeyCode::Char(' ') | KeyCode::Char('f') => top += page,
+            KeyCode::PageUp | KeyCode::Char('b') => top = top.saturating_sub(page),
+            KeyCode::Home | KeyCode::Char('g') => top = 0,
+            KeyCode::End | KeyCode::Char('G') => top = usize::MAX,
+            KeyCode::Char('/') => {
+                search = prompt(stdout, rows)?.filter(|pattern| !pattern.is_empty());
+            }
+            KeyCode::Char('n') => {}
+            _ => continue,
+        }
+
+        // Searching jumps to the next line containing the pattern
+        if matches!(key.code, KeyCode::Char('/') | KeyCode::Char('n')) {
+            if let Some(pattern) = &search {
+                match plain_lines
+                    .iter()
+                    .enumerate()
+                    .skip(top + 1)
+                    .find(|(_, line)| line.contains(pattern.as_str()))
+                {
+                    Some((i, _)) => top = i,
+                    None => message = Some("Pattern not found".to_string()),
+                }
+            }
+        }
+    }
+}
+
+fn draw(stdout: &mut Stdout, lines: &[&str], rows: u16, status: &str) -> std::io::Result<()> {
+    queue!(stdout, Clear(ClearType::All))?;
+
+    for (i, line) in lines.iter().enumerate() {
+        queue!(stdout, MoveTo(0, i as u16), Print(line))?;
+    }
+
+    queue!(
+        stdout,
+        MoveTo(0, rows.saturating_sub(1)),
+        SetAttribute(Attribute::Reset),
+        SetAttribute(Attribute::Reverse),
+        Print(status),
+        SetAttribute(Attribute::Reset),
+    )?;
+
+    stdout.flush()
+}
+
+/// Reads a search pattern on the bottom line, returns `None` if cancelled with escape
+fn prompt(stdout: &mut Stdout, rows: u16) -> std::io::Result<Option<String>> {
+    let mut input = String::new();
+
+    loop {
+        queue!(
+            stdout,
+            MoveTo(0, rows.saturating_sub(1)),
+            Clear(ClearType::CurrentLine),
+            Print(format!("/{}", input)),
+        )?;
+        stdout.flush()?;
+
+        let Event::Key(key) = read()? else {
+            continue;
+        };
+
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match key.code {
+            KeyCode::Enter => return Ok(Some(input)),
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+    }
+}
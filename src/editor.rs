@@ -0,0 +1,41 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::Command;
+
+use crate::parser::GZIP_MAGIC;
+
+/// Opens a log file in an editor, at its last line
+///
+/// `editor` is a command line such as "vim" or "code -w", it's split on whitespace. The last line
+/// is given as "+N", which vi, Emacs, nano and most others understand.
+///
+/// Returns the exit code of the editor.
+///
+pub fn edit(filepath: &Path, editor: &str) -> Result<i32, String> {
+    let mut file = File::open(filepath).map_err(|err| err.to_string())?;
+
+    // A compressed file would just be binary garbage in the editor
+    let mut magic = [0; GZIP_MAGIC.len()];
+    if file.read_exact(&mut magic).is_ok() && magic == GZIP_MAGIC {
+        return Err(format!(
+            "Can't edit {}, it's compressed",
+            filepath.display()
+        ));
+    }
+
+    let file = File::open(filepath).map_err(|err| err.to_string())?;
+    let line_count = BufReader::new(file).split(b'\n').count().max(1);
+
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or("The editor command is empty")?;
+    let status = Command::new(program)
+        .args(words)
+        .arg(format!("+{}", line_count))
+        .arg(filepath)
+        .status()
+        .map_err(|err| format!("Can't start the editor \"{}\": {}", program, err))?;
+
+    // Killed by a signal, there's no exit code
+    Ok(status.code().unwrap_or(128))
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+
+/// Matches "key=value", where the value may be double quoted
+static EQUALS_FIELD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?:^|\s)(?P<key>[A-Za-z_][\w.-]*)=(?P<value>"[^"]*"|[^\s,;"]+)"#).unwrap()
+});
+
+/// Matches "key: value", but only when the value contains a digit. Prose such as "Error: failed"
+/// is common in log messages, while IDs and counts are what we're after.
+static COLON_FIELD: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|\s)(?P<key>[A-Za-z_][\w.-]*): (?P<value>[^\s,;]*\d[^\s,;]*)").unwrap()
+});
+
+/// Extracts "key=value" and "key: value" pairs from a log message
+///
+/// This is conservative to avoid mangling prose: keys must be identifiers, values can't contain
+/// whitespace unless (for "key=value") they're double quoted, and "key: value" is only accepted
+/// when the value contains a digit. If a key occurs more than once, the first value wins.
+///
+pub fn extract_fields(message: &str) -> BTreeMap<String, String> {
+    let mut fields = BTreeMap::new();
+
+    for regex in [&*EQUALS_FIELD, &*COLON_FIELD] {
+        for captures in regex.captures_iter(message) {
+            let value = captures["value"].trim_matches('"');
+            fields
+                .entry(captures["key"].to_string())
+                .or_insert_with(|| value.to_string());
+        }
+    }
+
+    fields
+}
+
+/// Passes the key of every field in `line` through `paint`, leaving the rest of the line as-is
+pub fn paint_keys(line: &str, paint: impl Fn(&str) -> String) -> String {
+    let paint_key = |captures: &Captures, separator: &str| {
+        let whole = &captures[0];
+        let key = &captures["key"];
+        let key_start = captures.name("key").unwrap().start() - captures.get(0).unwrap().start();
+        let value_start = key_start + key.len() + separator.len();
+
+        format!(
+            "{}{}{}{}",
+            &whole[..key_start],
+            paint(key),
+            separator,
+            &whole[value_start..]
+        )
+    };
+
+    let line = EQUALS_FIELD.replace_all(line, |captures: &Captures| paint_key(captures, "="));
+    let line = COLON_FIELD.replace_all(&line, |captures: &Captures| paint_key(captures, ": "));
+
+    line.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(message: &str) -> Vec<(String, String)> {
+        extract_fields(message).into_iter().collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn equals_fields() {
+        assert_eq!(
+            fields(r#"Deploying service=l3vpn name="my vpn" count=3, dry-run=true;"#),
+            pairs(&[
+                ("count", "3"),
+                ("dry-run", "true"),
+                ("name", "my vpn"),
+                ("service", "l3vpn"),
+            ])
+        );
+    }
+
+    #[test]
+    fn colon_fields_need_a_digit() {
+        assert_eq!(
+            fields("Error: failed to commit, transaction id: 1234 device: pe-1"),
+            pairs(&[("device", "pe-1"), ("id", "1234")])
+        );
+        assert!(fields("Status: failed").is_empty());
+    }
+
+    #[test]
+    fn first_value_wins() {
+        assert_eq!(fields("id=1 id=2 id: 3"), pairs(&[("id", "1")]));
+    }
+
+    #[test]
+    fn prose_is_left_alone() {
+        for message in [
+            "",
+            "Nothing to see here: really",
+            "a == b",
+            "x=",
+            "1st=first",
+        ] {
+            assert!(fields(message).is_empty(), "{}", message);
+        }
+        // Only the outer pair is a field
+        assert_eq!(
+            fields("url=http://host/?a=b"),
+            pairs(&[("url", "http://host/?a=b")])
+        );
+    }
+
+    #[test]
+    fn paint_keys_only_touches_keys() {
+        let painted = paint_keys("Deploying service=l3vpn id: 12 Error: failed", |key| {
+            format!("[{}]", key)
+        });
+
+        assert_eq!(painted, "Deploying [service]=l3vpn [id]: 12 Error: failed");
+    }
+}
@@ -0,0 +1,708 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use regex::Regex;
+
+use crate::formatting::Timezone;
+use crate::parser::LogLine;
+
+/// Formats accepted by `parse_timestamp`, tried in order
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%dT%H:%M",
+    // The format used in the NSO logs themselves
+    "%d-%b-%Y::%H:%M:%S%.f",
+];
+
+/// Parses a user supplied timestamp, interpreting it in the given timezone
+///
+/// Accepts "YYYY-MM-DD HH:MM[:SS[.mmm]]" (with a space or a "T"), the date alone (meaning
+/// midnight), the NSO log format "DD-Mon-YYYY::HH:MM:SS.mmm" and RFC 3339.
+///
+pub fn parse_timestamp(input: &str, timezone: &Timezone) -> Result<DateTime<Utc>, String> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(input) {
+        return Ok(datetime.to_utc());
+    }
+
+    let naive = DATETIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(input, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| format!("Invalid timestamp: {}", input))?;
+
+    in_timezone(naive, timezone)
+        .ok_or_else(|| format!("Timestamp doesn't exist in local time: {}", input))
+}
+
+/// Parses a duration such as "90s", "15m", "1h" or "1h30m"
+///
+/// The units are "s", "m", "h", "d" and "w".
+///
+pub fn parse_duration(input: &str) -> Result<TimeDelta, String> {
+    let invalid = || format!("Invalid duration: {}", input);
+    let mut duration = TimeDelta::zero();
+    let mut rest = input;
+
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let amount: i64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit = rest[digits..].chars().next().ok_or_else(invalid)?;
+
+        let part = match unit {
+            's' => TimeDelta::try_seconds(amount),
+            'm' => TimeDelta::try_minutes(amount),
+            'h' => TimeDelta::try_hours(amount),
+            'd' => TimeDelta::try_days(amount),
+            'w' => TimeDelta::try_weeks(amount),
+            _ => None,
+        };
+
+        duration = part
+            .and_then(|part| duration.checked_add(&part))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + unit.len_utf8()..];
+    }
+
+    Ok(duration)
+}
+
+/// Midnight at the start of the day, `days_ago` days before today, in the given timezone
+pub fn start_of_day(days_ago: u64, timezone: &Timezone) -> Result<DateTime<Utc>, String> {
+    let today = match timezone {
+        Timezone::Utc => Utc::now().date_naive(),
+        Timezone::Local => Local::now().date_naive(),
+        Timezone::Fixed(offset) => Utc::now().with_timezone(offset).date_naive(),
+    };
+
+    today
+        .checked_sub_days(Days::new(days_ago))
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| in_timezone(midnight, timezone))
+        .ok_or_else(|| "Midnight doesn't exist in local time".to_string())
+}
+
+fn in_timezone(naive: NaiveDateTime, timezone: &Timezone) -> Option<DateTime<Utc>> {
+    match timezone {
+        Timezone::Utc => Some(naive.and_utc()),
+        Timezone::Local => Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|datetime| datetime.to_utc()),
+        Timezone::Fixed(offset) => offset
+            .from_local_datetime(&naive)
+            .single()
+            .map(|datetime| datetime.to_utc()),
+    }
+}
+
+/// An inclusive time range, either end can be left open
+#[derive(Debug, Default)]
+pub struct TimeRange {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    pub fn is_active(&self) -> bool {
+        self.since.is_some() || self.until.is_some()
+    }
+}
+
+/// Filters log lines by time as they stream past, without buffering anything
+///
+/// Dangling lines are shown if the log message before them was in range. Dangling lines at the
+/// very start of the log are only shown if the range has no lower bound.
+///
+pub struct TimeFilter<I: Iterator<Item = LogLine>> {
+    inner: I,
+    range: TimeRange,
+    /// Stop reading as soon as a log message is past the end of the range, since log files are
+    /// ordered. This must be disabled while following, or we'd stop following.
+    stop_early: bool,
+    in_range: bool,
+}
+
+impl<I: Iterator<Item = LogLine>> TimeFilter<I> {
+    pub fn new(inner: I, range: TimeRange, stop_early: bool) -> Self {
+        let in_range = range.since.is_none();
+
+        Self {
+            inner,
+            range,
+            stop_early,
+            in_range,
+        }
+    }
+}
+
+impl<I: Iterator<Item = LogLine>> Iterator for TimeFilter<I> {
+    type Item = LogLine;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let logline = self.inner.next()?;
+
+            if let LogLine::Normal(logline) = &logline {
+                let datetime = logline.datetime;
+
+                if self.range.until.is_some_and(|until| datetime > until) {
+                    if self.stop_early {
+                        return None;
+                    }
+
+                    self.in_range = false;
+                } else {
+                    self.in_range = self.range.since.is_none_or(|since| datetime >= since);
+                }
+            }
+
+            if self.in_range {
+                return Some(logline);
+            }
+        }
+    }
+}
+
+/// Skips the dangling lines at the start of the input, which are the end of a log message that
+/// started before it
+///
+/// The dangling lines are held back until the first log message, and let through after all if
+/// there are more than `limit` of them or the input ends first, since then the input is probably
+/// not an NSO log at all.
+///
+pub struct SkipPartial<I: Iterator<Item = LogLine>> {
+    inner: I,
+    limit: usize,
+    /// The leading dangling lines, `None` once they've been skipped or let through
+    held: Option<Vec<LogLine>>,
+    /// Lines to send on before reading any more
+    queue: VecDeque<LogLine>,
+}
+
+impl<I: Iterator<Item = LogLine>> SkipPartial<I> {
+    pub fn new(inner: I, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            held: Some(Vec::new()),
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = LogLine>> Iterator for SkipPartial<I> {
+    type Item = LogLine;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(logline) = self.queue.pop_front() {
+            return Some(logline);
+        }
+
+        let Some(held) = &mut self.held else {
+            return self.inner.next();
+        };
+
+        loop {
+            match self.inner.next() {
+                Some(logline @ LogLine::Normal(_)) => {
+                    self.held = None;
+                    return Some(logline);
+                }
+                Some(logline) => {
+                    held.push(logline);
+                    if held.len() > self.limit {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        self.queue.extend(self.held.take().unwrap_or_default());
+        self.queue.pop_front()
+    }
+}
+
+/// Filters log lines by a regex, like "grep", optionally with log messages of context around the
+/// matches, like "grep -B" and "grep -A"
+///
+/// Context is counted in log messages. Dangling lines are shown if the log message before them
+/// was shown, so they're included in the context without counting towards it. A dangling line
+/// that matches the regex counts as a match. Without a regex everything is let through.
+///
+/// When inverted, log messages that don't match are the matches instead, like "grep -v". A
+/// multi-line message is hidden if any of its lines match. Dangling lines go with the log message
+/// before them, so the end of a hidden message isn't shown on its own. Only the dangling lines
+/// before the first log message are judged by themselves.
+///
+pub struct GrepFilter<I: Iterator<Item = LogLine>> {
+    inner: I,
+    regex: Option<Regex>,
+    invert: bool,
+    before: usize,
+    after: usize,
+    /// Lines that may be shown as context before the next match, at most `before` log messages
+    /// and the dangling lines after them
+    history: VecDeque<LogLine>,
+    /// How many more log messages to show after the last match
+    after_remaining: usize,
+    /// Whether the last log message was shown, which decides whether the dangling lines
+    /// after it are shown
+    showing: bool,
+    /// Whether there's been a log message yet, for deciding what to do with dangling lines when
+    /// inverted
+    seen_message: bool,
+    /// Lines to send on before reading any more
+    queue: VecDeque<LogLine>,
+}
+
+impl<I: Iterator<Item = LogLine>> GrepFilter<I> {
+    pub fn new(inner: I, regex: Option<Regex>, before: usize, after: usize) -> Self {
+        Self {
+            inner,
+            regex,
+            invert: false,
+            before,
+            after,
+            history: VecDeque::new(),
+            after_remaining: 0,
+            showing: false,
+            seen_message: false,
+            queue: VecDeque::new(),
+        }
+    }
+
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    fn remember(&mut self, logline: LogLine) {
+        if self.before == 0 {
+            return;
+        }
+
+        self.history.push_back(logline);
+
+        let messages = |history: &VecDeque<LogLine>| {
+            history
+                .iter()
+                .filter(|logline| matches!(logline, LogLine::Normal(_)))
+                .count()
+        };
+
+        // Forget the oldest log message along with its dangling lines, and any dangling lines
+        // that came before it
+        while messages(&self.history) > self.before {
+            self.history.pop_front();
+            while matches!(self.history.front(), Some(LogLine::Dangling(_))) {
+                self.history.pop_front();
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = LogLine>> Iterator for GrepFilter<I> {
+    type Item = LogLine;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(logline) = self.queue.pop_front() {
+                return Some(logline);
+            }
+
+            let logline = self.inner.next()?;
+            let Some(regex) = &self.regex else {
+                return Some(logline);
+            };
+            let is_match = match &logline {
+                LogLine::Normal(logline) => {
+                    self.seen_message = true;
+                    self.invert != regex.is_match(&logline.message)
+                }
+                // Shown if the log message before it was, below
+                LogLine::Dangling(_) if self.invert && self.seen_message => false,
+                LogLine::Dangling(dangling) => self.invert != regex.is_match(&dangling.text),
+            };
+
+            if is_match {
+                self.queue.extend(self.history.drain(..));
+                self.after_remaining = self.after;
+                self.showing = true;
+                self.queue.push_back(logline);
+                continue;
+            }
+
+            match logline {
+                LogLine::Normal(_) if self.after_remaining > 0 => {
+                    self.after_remaining -= 1;
+                    self.showing = true;
+                    return Some(logline);
+                }
+                LogLine::Normal(_) => {
+                    self.showing = false;
+                    self.remember(logline);
+                }
+                // Dangling lines go with the log message before them. When inverted, there has to
+                // be one, leading dangling lines are judged by themselves above.
+                LogLine::Dangling(_) if self.showing && (self.seen_message || !self.invert) => {
+                    return Some(logline)
+                }
+                LogLine::Dangling(_) => self.remember(logline),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::parser::{parse_str, DanglingLogLine, LineFormat, MessageDelimiter};
+
+    fn parse(text: &str) -> Vec<LogLine> {
+        parse_str(text, LineFormat::Nso(MessageDelimiter::Either)).collect()
+    }
+
+    fn render(loglines: impl Iterator<Item = LogLine>) -> Vec<String> {
+        loglines.map(|logline| logline.to_string()).collect()
+    }
+
+    const LOG: &str = "\
+<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Starting up
+<DEBUG> 02-Jan-2024::15:04:06.456 my-logger Thread-1: - Something happened
+Traceback line 1
+  line 2
+<ERROR> 02-Jan-2024::15:05:00.000 other ThreadPool-2: - Boom
+<WARNING> 02-Jan-2024::16:00:00.000 my-logger MainThread: - Careful
+";
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().to_utc()
+    }
+
+    #[test]
+    fn parse_timestamp_formats() {
+        for input in [
+            "2024-01-02 15:04:05.123",
+            "2024-01-02T15:04:05.123",
+            "02-Jan-2024::15:04:05.123",
+            "2024-01-02T15:04:05.123Z",
+            "2024-01-02T17:04:05.123+02:00",
+        ] {
+            assert_eq!(
+                parse_timestamp(input, &Timezone::Utc),
+                Ok(utc("2024-01-02T15:04:05.123Z")),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            parse_timestamp("2024-01-02 15:04", &Timezone::Utc),
+            Ok(utc("2024-01-02T15:04:00Z"))
+        );
+        assert_eq!(
+            parse_timestamp("2024-01-02", &Timezone::Utc),
+            Ok(utc("2024-01-02T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn parse_timestamp_in_a_timezone() {
+        let plus_two = Timezone::Fixed(chrono::FixedOffset::east_opt(2 * 3600).unwrap());
+
+        assert_eq!(
+            parse_timestamp("2024-01-02 15:04", &plus_two),
+            Ok(utc("2024-01-02T13:04:00Z"))
+        );
+        // An explicit offset wins over the timezone
+        assert_eq!(
+            parse_timestamp("2024-01-02T15:04:00Z", &plus_two),
+            Ok(utc("2024-01-02T15:04:00Z"))
+        );
+    }
+
+    #[test]
+    fn parse_timestamp_rejects_garbage() {
+        for input in ["", "yesterday", "2024-13-01", "2024-01-02 25:00", "15:04"] {
+            assert_eq!(
+                parse_timestamp(input, &Timezone::Utc),
+                Err(format!("Invalid timestamp: {}", input))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("90s"), Ok(TimeDelta::seconds(90)));
+        assert_eq!(parse_duration("15m"), Ok(TimeDelta::minutes(15)));
+        assert_eq!(parse_duration("1h30m"), Ok(TimeDelta::minutes(90)));
+        assert_eq!(parse_duration("2d"), Ok(TimeDelta::days(2)));
+        assert_eq!(
+            parse_duration("1w1s"),
+            Ok(TimeDelta::seconds(7 * 86400 + 1))
+        );
+        assert_eq!(parse_duration("0s"), Ok(TimeDelta::zero()));
+    }
+
+    #[test]
+    fn parse_duration_rejects_garbage() {
+        for input in [
+            "",
+            "90",
+            "m",
+            "1x",
+            "1h30",
+            "-1h",
+            "1.5h",
+            "1 h",
+            "99999999999999999999s",
+        ] {
+            assert_eq!(
+                parse_duration(input),
+                Err(format!("Invalid duration: {}", input))
+            );
+        }
+        // Too large for a TimeDelta
+        assert!(parse_duration(&format!("{}w", i64::MAX)).is_err());
+    }
+
+    #[test]
+    fn skip_partial_skips_leading_dangling_lines() {
+        let loglines = parse("  end of a traceback\n  more of it\n<INFO> 02-Jan-2024::15:04:05.123 a b: - Hi\nlater\n");
+        let shown = render(SkipPartial::new(loglines.into_iter(), 20));
+
+        assert_eq!(shown.len(), 1);
+        assert!(shown[0].ends_with("Hi\nlater"), "{:?}", shown);
+    }
+
+    #[test]
+    fn skip_partial_lets_through_input_without_log_messages() {
+        let loglines = parse("just\nplain\ntext\n");
+        let shown = render(SkipPartial::new(loglines.into_iter(), 20));
+
+        assert_eq!(shown, ["just", "plain", "text"]);
+    }
+
+    #[test]
+    fn skip_partial_gives_up_after_the_limit() {
+        let loglines = parse("1\n2\n3\n4\n<INFO> 02-Jan-2024::15:04:05.123 a b: - Hi\n");
+        let shown = render(SkipPartial::new(loglines.into_iter(), 2));
+
+        assert_eq!(shown.len(), 5);
+        assert_eq!(shown[..4], ["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn skip_partial_keeps_a_complete_log() {
+        let shown = render(SkipPartial::new(parse(LOG).into_iter(), 20));
+
+        assert_eq!(shown, render(parse(LOG).into_iter()));
+    }
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        parse_timestamp(timestamp, &Timezone::Utc).unwrap()
+    }
+
+    /// An endless log, one message per second, counting how many messages have been read
+    fn endless_log(read: &Cell<usize>) -> impl Iterator<Item = LogLine> + '_ {
+        (0..).map(move |second| {
+            read.set(read.get() + 1);
+            let text = format!(
+                "<INFO> 02-Jan-2024::15:{:02}:{:02}.000 a b: - Message {}\n",
+                second / 60,
+                second % 60,
+                second
+            );
+            parse(&text).pop().unwrap()
+        })
+    }
+
+    #[test]
+    fn time_filter_stops_at_the_end_of_the_range() {
+        let read = Cell::new(0);
+        let range = TimeRange {
+            since: Some(at("2024-01-02 15:00:10")),
+            until: Some(at("2024-01-02 15:00:19")),
+        };
+        let shown = render(TimeFilter::new(endless_log(&read), range, true));
+
+        assert_eq!(shown.len(), 10);
+        assert!(shown[0].ends_with("Message 10"));
+        assert!(shown[9].ends_with("Message 19"));
+        // Only the first message past the end of the range was read
+        assert_eq!(read.get(), 21);
+    }
+
+    #[test]
+    fn time_filter_streams_while_following() {
+        let read = Cell::new(0);
+        let range = TimeRange {
+            since: Some(at("2024-01-02 15:00:10")),
+            until: Some(at("2024-01-02 15:00:19")),
+        };
+        let mut filter = TimeFilter::new(endless_log(&read), range, false);
+
+        // Each message is let through as soon as it's read, nothing is collected
+        assert!(filter.next().unwrap().to_string().ends_with("Message 10"));
+        assert_eq!(read.get(), 11);
+        assert!(filter.next().unwrap().to_string().ends_with("Message 11"));
+        assert_eq!(read.get(), 12);
+    }
+
+    /// Log lines from a list where log messages are given by their message, and dangling lines
+    /// start with "|"
+    fn loglines(spec: &[&str]) -> Vec<LogLine> {
+        spec.iter()
+            .map(|line| match line.strip_prefix('|') {
+                Some(text) => LogLine::Dangling(DanglingLogLine::new(text.to_string())),
+                None => parse(&format!(
+                    "<INFO> 02-Jan-2024::15:04:05.123 a b: - {}\n",
+                    line
+                ))
+                .pop()
+                .unwrap(),
+            })
+            .collect()
+    }
+
+    /// What a grep filter shows, in the same format as `loglines`
+    fn grep(
+        spec: &[&str],
+        pattern: &str,
+        before: usize,
+        after: usize,
+        invert: bool,
+    ) -> Vec<String> {
+        let regex = Regex::new(pattern).unwrap();
+        GrepFilter::new(loglines(spec).into_iter(), Some(regex), before, after)
+            .invert(invert)
+            .map(|logline| match logline {
+                LogLine::Normal(logline) => logline.message,
+                LogLine::Dangling(dangling) => format!("|{}", dangling.text),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grep_without_context() {
+        let spec = ["one", "match", "|dangling", "two", "match again"];
+
+        assert_eq!(
+            grep(&spec, "match", 0, 0, false),
+            ["match", "|dangling", "match again"]
+        );
+    }
+
+    #[test]
+    fn after_context_skips_over_dangling_lines() {
+        let spec = [
+            "match", "|d1", "|d2", "one", "|d3", "two", "|d4", "three", "match", "four", "five",
+        ];
+
+        assert_eq!(
+            grep(&spec, "match", 0, 2, false),
+            ["match", "|d1", "|d2", "one", "|d3", "two", "|d4", "match", "four", "five"]
+        );
+    }
+
+    #[test]
+    fn before_context_includes_dangling_lines() {
+        let spec = ["one", "|d1", "two", "|d2", "three", "|d3", "match", "four"];
+
+        assert_eq!(
+            grep(&spec, "match", 2, 0, false),
+            ["two", "|d2", "three", "|d3", "match"]
+        );
+    }
+
+    #[test]
+    fn overlapping_context_is_shown_once() {
+        let spec = ["one", "match", "two", "match", "three", "four"];
+
+        assert_eq!(
+            grep(&spec, "match", 1, 1, false),
+            ["one", "match", "two", "match", "three"]
+        );
+    }
+
+    #[test]
+    fn dangling_lines_can_match() {
+        let spec = ["one", "two", "|a match", "three", "four"];
+
+        assert_eq!(
+            grep(&spec, "match", 1, 1, false),
+            ["two", "|a match", "three"]
+        );
+    }
+
+    #[test]
+    fn invert() {
+        let spec = ["one", "noise", "two", "more noise", "three"];
+
+        assert_eq!(grep(&spec, "noise", 0, 0, true), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn invert_hides_multi_line_messages_with_any_matching_line() {
+        let loglines = parse(
+            "<INFO> 02-Jan-2024::15:04:05.123 a b: - Keep\n  this\n\
+             <INFO> 02-Jan-2024::15:04:06.000 a b: - Hide\n  because of noise\n\
+             <INFO> 02-Jan-2024::15:04:07.000 a b: - Keep\n",
+        );
+        let shown = GrepFilter::new(
+            loglines.into_iter(),
+            Some(Regex::new("noise").unwrap()),
+            0,
+            0,
+        )
+        .invert(true)
+        .map(|logline| match logline {
+            LogLine::Normal(logline) => logline.message,
+            LogLine::Dangling(_) => panic!("Dangling line"),
+        })
+        .collect::<Vec<_>>();
+
+        assert_eq!(shown, ["Keep\n  this", "Keep"]);
+    }
+
+    #[test]
+    fn invert_keeps_dangling_lines_with_their_message() {
+        let spec = ["noise", "|end of it", "one", "|d1", "|noisy d2", "two"];
+
+        assert_eq!(
+            grep(&spec, "noise", 0, 0, true),
+            ["one", "|d1", "|noisy d2", "two"]
+        );
+    }
+
+    #[test]
+    fn invert_judges_leading_dangling_lines_by_themselves() {
+        let spec = ["|plain", "|noise", "|text", "one"];
+
+        assert_eq!(grep(&spec, "noise", 0, 0, true), ["|plain", "|text", "one"]);
+    }
+
+    #[test]
+    fn invert_with_context() {
+        let spec = ["noise 1", "|d1", "one", "noise 2", "noise 3", "noise 4"];
+
+        assert_eq!(
+            grep(&spec, "noise", 1, 1, true),
+            ["noise 1", "|d1", "one", "noise 2"]
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::mpsc;
+use std::thread;
+
+use crate::parser::LogLine;
+
+/// Merges the log lines of several files as they arrive, tagging each with its file name
+///
+/// Each file is read in its own thread, since reading blocks until the file grows. The merged
+/// stream ends once all of the files have ended, which while following means once the "tail"
+/// children are gone.
+///
+pub fn follow_many(
+    sources: Vec<(String, Box<dyn Iterator<Item = LogLine> + Send>)>,
+) -> impl Iterator<Item = LogLine> {
+    let (sender, receiver) = mpsc::channel();
+
+    for (filename, loglines) in sources {
+        let sender = sender.clone();
+
+        thread::spawn(move || {
+            for mut logline in loglines {
+                logline.set_file(filename.clone());
+
+                // The receiver is gone, we're shutting down
+                if sender.send(logline).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    receiver.into_iter()
+}
@@ -1,9 +1,20 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fmt::Write as _;
 use std::io::Write;
+use std::sync::LazyLock;
 
-use owo_colors::colors::{Blue, Green, Magenta, Red, Yellow};
-use owo_colors::OwoColorize;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, TimeDelta, Utc};
 
-use crate::parser::{LogLine, Severity};
+use owo_colors::colors::{Blue, Cyan, Green, Magenta, Red, Yellow};
+use owo_colors::{AnsiColors, OwoColorize};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::ansi::{display_width, hard_wrap};
+use crate::fields::paint_keys;
+use crate::parser::{line_severity, LogLine, ParseLineError, Severity};
 
 type DebugColor = Magenta;
 type InfoColor = Green;
@@ -14,12 +25,328 @@ type ErrorColor = Red;
 pub enum DateFormat {
     Full,
     TimeOnly,
+    /// A user supplied chrono strftime string
+    Custom(String),
+    /// The time since the first log message printed, like "dmesg". The baseline is set by the
+    /// first log message printed, and is shared by all files when following several.
+    Relative(Cell<Option<DateTime<Utc>>>),
+}
+
+impl DateFormat {
+    fn strftime(&self) -> &str {
+        match self {
+            DateFormat::Full | DateFormat::Relative(_) => "%Y-%m-%d %H:%M:%S%.3f",
+            DateFormat::TimeOnly => "%H:%M:%S%.3f",
+            DateFormat::Custom(format) => format,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum TimestampMode {
+    /// The time of each log message
+    Absolute,
+    /// The time since the first log message shown, e.g. "+00:00:01.234"
+    Relative,
+}
+
+#[derive(Debug)]
+pub enum Timezone {
+    Utc,
+    Local,
+    /// A fixed offset from UTC, see `timezone_from_filename`
+    Fixed(FixedOffset),
+}
+
+/// Matches a timezone in a file name for "--tz-from-filename", e.g. "utc", "UTC+2" or
+/// "gmt-05:30", separated from the rest of the name by ".", "-" or "_"
+///
+/// Anything that looks like an offset is captured, `offset_seconds` decides whether it is one.
+///
+static FILENAME_TIMEZONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(?:^|[._-])(?:utc|gmt)(?:([+-])([0-9:]+))?(?:[._-]|$)").unwrap()
+});
+
+/// Finds the timezone in a file name such as "ncs-java-vm.utc+0530.log", best-effort
+///
+/// Only UTC or GMT with an optional offset in hours, or hours and minutes, is recognized. Named
+/// zones such as "CET" would need a timezone database, so they aren't.
+///
+pub fn timezone_from_filename(filename: &str) -> Option<Timezone> {
+    FILENAME_TIMEZONE
+        .captures_iter(filename)
+        .find_map(|captures| {
+            let Some(sign) = captures.get(1) else {
+                return Some(Timezone::Utc);
+            };
+
+            match (sign.as_str(), offset_seconds(&captures[2])) {
+                ("-", Some(seconds)) => FixedOffset::west_opt(seconds).map(Timezone::Fixed),
+                (_, Some(seconds)) => FixedOffset::east_opt(seconds).map(Timezone::Fixed),
+                // Not an offset, e.g. "ncs-utc-2024-01-02.log", the "-" only separated a bare "utc"
+                ("-", None) => Some(Timezone::Utc),
+                (_, None) => None,
+            }
+        })
+}
+
+/// Parses an offset of "H", "HH", "HHMM", "H:MM" or "HH:MM" into seconds, at most 14:59
+fn offset_seconds(offset: &str) -> Option<i32> {
+    let (hours, minutes) = match offset.split_once(':') {
+        Some((hours, minutes)) if minutes.len() == 2 => (hours, minutes),
+        Some(_) => return None,
+        None if offset.len() == 4 => offset.split_at(2),
+        None => (offset, "0"),
+    };
+    if hours.is_empty() || hours.len() > 2 {
+        return None;
+    }
+
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+
+    (hours <= 14 && minutes <= 59).then_some((hours * 60 + minutes) * 60)
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Aligned, colorized columns
+    Pretty,
+    /// One JSON object per line, can be read back with "--input-format jsonl"
+    Json,
+    /// A header row followed by one row per log message
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum FieldSeparator {
+    Comma,
+    Tab,
+}
+
+impl FieldSeparator {
+    fn byte(&self) -> u8 {
+        match self {
+            FieldSeparator::Comma => b',',
+            FieldSeparator::Tab => b'\t',
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum LabelStyle {
+    /// Abbreviated 4-character labels, e.g. "WARN"
+    Short,
+    /// Full severity names, e.g. "WARNING"
+    Long,
+    /// A single colored glyph, e.g. "▲" (or "!" with "--ascii")
+    Icons,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ColorBy {
+    /// Color each log message by its severity
+    Severity,
+    /// Give each logger its own color, only the label shows the severity
+    Logger,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Column {
+    Severity,
+    Time,
+    /// Time since the previous log message shown
+    Elapsed,
+    Logger,
+    Thread,
+    Message,
+}
+
+pub const DEFAULT_COLUMNS: &[Column] = &[
+    Column::Severity,
+    Column::Time,
+    Column::Logger,
+    Column::Thread,
+    Column::Message,
+];
+
+/// Options controlling how log lines are rendered
+#[derive(Debug)]
+pub struct FormatOptions {
+    pub dateformat: DateFormat,
+    pub timezone: Timezone,
+    pub labels: LabelStyle,
+    /// Only output ASCII, for terminals with poor Unicode support
+    pub ascii: bool,
+    /// Wrap single-line messages that would be wider than this
+    pub wrap_width: Option<usize>,
+    /// Wrap the lines of multi-line messages that would be wider than this
+    pub box_wrap_width: Option<usize>,
+    /// Which columns to show, in order
+    pub columns: Vec<Column>,
+    /// How many spaces to indent the box around multi-line messages with
+    pub box_indent: usize,
+    /// Highlight elapsed times longer than this
+    pub elapsed_threshold: TimeDelta,
+    /// Patterns to highlight in messages, each in its own color
+    pub highlights: Vec<Regex>,
+    /// Prefix log lines with the name of their file, if they have one
+    pub prefix_file: bool,
+    /// Indent JSON output, rather than printing one object per line
+    pub json_pretty: bool,
+    /// Separates the fields of CSV output
+    pub field_separator: FieldSeparator,
+    pub color_by: ColorBy,
+    /// Mark dangling lines that look like the start of a log message but couldn't be parsed
+    pub mark_unparsed: bool,
+    /// Put multi-line messages on a single line, with line breaks replaced by this
+    pub compact: Option<String>,
+    /// Print only the message of each log line
+    pub message_only: bool,
+    /// Output is colored where color is optional, "--message-only" and "--highlight", i.e. when
+    /// STDOUT is a terminal and NO_COLOR isn't set
+    pub use_color: bool,
+}
+
+/// The background colors of "--highlight" patterns, used in turn
+const HIGHLIGHT_COLORS: &[AnsiColors] = &[
+    AnsiColors::Yellow,
+    AnsiColors::Cyan,
+    AnsiColors::Magenta,
+    AnsiColors::Green,
+    AnsiColors::Blue,
+    AnsiColors::Red,
+];
+
+/// The colors handed out to loggers by "--color-by logger". Red is left out so errors still stand
+/// out, and so is black, which is unreadable on many terminals.
+const LOGGER_COLORS: &[AnsiColors] = &[
+    AnsiColors::Green,
+    AnsiColors::Yellow,
+    AnsiColors::Blue,
+    AnsiColors::Magenta,
+    AnsiColors::Cyan,
+    AnsiColors::BrightGreen,
+    AnsiColors::BrightYellow,
+    AnsiColors::BrightBlue,
+    AnsiColors::BrightMagenta,
+    AnsiColors::BrightCyan,
+];
+
+/// Picks a color for a logger by hashing its name, so it gets the same color every time
+///
+/// This is FNV-1a rather than the standard library's hasher, which isn't guaranteed to give the
+/// same result between Rust versions.
+///
+fn logger_color(logger_name: &str) -> AnsiColors {
+    let hash = logger_name
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+
+    LOGGER_COLORS[(hash % LOGGER_COLORS.len() as u64) as usize]
+}
+
+/// The version of the JSON output, bumped whenever a field is changed or removed. Adding a field
+/// doesn't count.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// A log line as printed by "--format json"
+#[derive(Serialize)]
+struct JsonLogLine<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    logline: &'a LogLine,
+}
+
+/// The default indentation in front of the glyphs of a multi-line message
+pub const DEFAULT_BOX_INDENT: usize = 3;
+
+impl LabelStyle {
+    fn width(&self) -> usize {
+        match self {
+            LabelStyle::Short => 4,
+            LabelStyle::Long => 8,
+            LabelStyle::Icons => 1,
+        }
+    }
+}
+
+fn label(labels: LabelStyle, severity: &Severity, ascii: bool) -> Cow<'static, str> {
+    let label = match (labels, severity) {
+        (LabelStyle::Short, Severity::Trace) => " TRC",
+        (LabelStyle::Short, Severity::Debug) => " DBG",
+        (LabelStyle::Short, Severity::Info) => "INFO",
+        (LabelStyle::Short, Severity::Warning) => "WARN",
+        (LabelStyle::Short, Severity::Error) => " ERR",
+        (LabelStyle::Short, Severity::Critical) => "CRIT",
+        (LabelStyle::Long, Severity::Trace) => "   TRACE",
+        (LabelStyle::Long, Severity::Debug) => "   DEBUG",
+        (LabelStyle::Long, Severity::Info) => "    INFO",
+        (LabelStyle::Long, Severity::Warning) => " WARNING",
+        (LabelStyle::Long, Severity::Error) => "   ERROR",
+        (LabelStyle::Long, Severity::Critical) => "CRITICAL",
+        (LabelStyle::Icons, Severity::Trace) if ascii => ".",
+        (LabelStyle::Icons, Severity::Debug) if ascii => "*",
+        (LabelStyle::Icons, Severity::Info) if ascii => "i",
+        (LabelStyle::Icons, Severity::Warning) if ascii => "!",
+        (LabelStyle::Icons, Severity::Error) if ascii => "x",
+        (LabelStyle::Icons, Severity::Critical) if ascii => "X",
+        (LabelStyle::Icons, Severity::Trace) => "·",
+        (LabelStyle::Icons, Severity::Debug) => "•",
+        (LabelStyle::Icons, Severity::Info) => "ℹ",
+        (LabelStyle::Icons, Severity::Warning) => "▲",
+        (LabelStyle::Icons, Severity::Error) => "✖",
+        (LabelStyle::Icons, Severity::Critical) => "‼",
+        // Cut or pad the raw token to the width of the other labels
+        (_, Severity::Unknown(token)) => {
+            let width = labels.width();
+            let token: String = token.chars().take(width).collect();
+            return Cow::Owned(format!("{:>width$}", token));
+        }
+    };
+
+    Cow::Borrowed(label)
+}
+
+/// Puts a multi-line text on a single line for "--compact", otherwise leaves it alone
+fn compact<'a>(text: &'a str, options: &FormatOptions) -> Cow<'a, str> {
+    match &options.compact {
+        Some(glyph) if text.contains('\n') => Cow::Owned(text.replace('\n', glyph)),
+        _ => Cow::Borrowed(text),
+    }
+}
+
+/// Writes a duration as "+HH:MM:SS.mmm", hours aren't wrapped at a day
+fn write_relative(target: &mut String, offset: TimeDelta) -> std::fmt::Result {
+    let sign = if offset < TimeDelta::zero() { '-' } else { '+' };
+    let millis = offset.abs().num_milliseconds();
+
+    write!(
+        target,
+        "{}{:02}:{:02}:{:02}.{:03}",
+        sign,
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Checks that a strftime string only contains specifiers chrono understands
+pub fn validate_strftime(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(format!("Invalid time format: {}", format));
+    }
+
+    Ok(())
 }
 
 pub fn print_logline(
     logline: &LogLine,
     target: &mut impl Write,
-    dateformat: &DateFormat,
+    options: &FormatOptions,
 ) -> std::io::Result<()> {
     // Shortcut for writing to 'target'
     macro_rules! put {
@@ -28,74 +355,233 @@ pub fn print_logline(
         };
     }
 
+    if options.message_only {
+        return print_message_only(logline, target, options);
+    }
+
     match logline {
         LogLine::Dangling(logline) => {
-            put!("{}", logline.text)?;
+            if let Some(file) = logline.file.as_ref().filter(|_| options.prefix_file) {
+                put!("{} ", format!("[{}]", file).dimmed())?;
+            }
+
+            if options.mark_unparsed && logline.parse_error == Some(ParseLineError::Malformed) {
+                let marker = match options.ascii {
+                    true => "! unparsed:",
+                    false => "⚠ unparsed:",
+                };
+                put!("{} ", marker.dimmed())?;
+            }
+
+            let text = compact(&logline.text, options);
+            put!("{}", highlight_patterns(&text, options))?;
         }
         LogLine::Normal(logline) => {
-            // Shortcut for writing to 'target' with the current severity color
-            macro_rules! putc {
+            let logger_color = match options.color_by {
+                ColorBy::Severity => None,
+                ColorBy::Logger => Some(logger_color(&logline.logger_name)),
+            };
+
+            // Shortcut for writing to 'target' with the severity color
+            macro_rules! put_severity {
                 ($string:expr) => {
                     match logline.severity {
+                        Severity::Trace => put!("{}", $string.fg::<DebugColor>().dimmed())?,
                         Severity::Debug => put!("{}", $string.fg::<DebugColor>())?,
                         Severity::Info => put!("{}", $string.fg::<InfoColor>())?,
                         Severity::Warning => put!("{}", $string.fg::<WarningColor>())?,
                         Severity::Error => put!("{}", $string.fg::<ErrorColor>())?,
                         Severity::Critical => put!("{}", $string.fg::<ErrorColor>())?,
+                        Severity::Unknown(_) => put!("{}", $string)?,
+                    }
+                };
+            }
+
+            // Shortcut for writing to 'target' with the current color, which is the color of the
+            // logger with "--color-by logger"
+            macro_rules! putc {
+                ($string:expr) => {
+                    match logger_color {
+                        Some(color) => put!("{}", $string.color(color))?,
+                        None => put_severity!($string),
                     }
                 };
             }
 
-            match logline.severity {
-                Severity::Debug => putc!(" DBG".bold()),
-                Severity::Info => putc!("INFO".bold()),
-                Severity::Warning => putc!("WARN".bold()),
-                Severity::Error => putc!(" ERR".bold()),
-                Severity::Critical => putc!("CRIT".bold()),
+            // Error messages are colored as a whole, unless the logger decides the color
+            let color_message = logger_color.is_none()
+                && matches!(logline.severity, Severity::Error | Severity::Critical);
+
+            // Render the timestamp up front, chrono reports an invalid format string as an opaque
+            // formatter error, so we replace it with something more helpful
+            let strftime = options.dateformat.strftime();
+            let mut datetime = String::new();
+            match (&options.dateformat, &options.timezone) {
+                (DateFormat::Relative(baseline), _) => {
+                    let start = baseline.get().unwrap_or(logline.datetime);
+                    baseline.set(Some(start));
+                    write_relative(&mut datetime, logline.datetime - start)
+                }
+                (_, Timezone::Utc) => write!(datetime, "{}", logline.datetime.format(strftime)),
+                (_, Timezone::Local) => write!(
+                    datetime,
+                    "{}",
+                    logline.datetime.with_timezone(&Local).format(strftime)
+                ),
+                (_, Timezone::Fixed(offset)) => write!(
+                    datetime,
+                    "{}",
+                    logline.datetime.with_timezone(offset).format(strftime)
+                ),
+            }
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid time format: {}", strftime),
+                )
+            })?;
+
+            // Highlight "--highlight" patterns, and the keys of extracted fields. Error messages are
+            // colored as a whole, so leave their keys alone.
+            let highlight = |line: &str| -> String {
+                let line = highlight_patterns(line, options);
+                match logline.fields.is_empty() || color_message {
+                    true => line.into_owned(),
+                    false => paint_keys(&line, |key| key.fg::<Cyan>().to_string()),
+                }
             };
 
-            put!(
-                " {}",
-                logline
-                    .datetime
-                    .format(match dateformat {
-                        DateFormat::Full => "%Y-%m-%d %H:%M:%S%.3f",
-                        DateFormat::TimeOnly => "%H:%M %S%.3f",
-                    })
-                    .fg::<Blue>()
-                    .bold()
-            )?;
-
-            put!(" {}", logline.logger_name.fg::<WarningColor>().bold())?;
-            put!(":")?;
-
-            if !logline.message.contains('\n') {
-                // Single-line message
-                match logline.severity {
-                    Severity::Error | Severity::Critical => {
-                        putc!(logline.message.fg::<ErrorColor>());
+            // How much has been printed in front of the message, for deciding whether to wrap it
+            let mut header_width = 0;
+
+            if let Some(file) = logline.file.as_ref().filter(|_| options.prefix_file) {
+                put!("{} ", format!("[{}]", file).dimmed())?;
+                header_width += display_width(file) + 3;
+            }
+            // Multi-line messages are drawn in a box below the other columns. So are single-line
+            // messages that don't fit, once they're wrapped.
+            let mut boxed: Option<Cow<str>> = None;
+
+            for (i, column) in options.columns.iter().enumerate() {
+                if i > 0 && *column != Column::Message {
+                    put!(" ")?;
+                    header_width += 1;
+                }
+
+                match column {
+                    Column::Severity => {
+                        put_severity!(
+                            label(options.labels, &logline.severity, options.ascii).bold()
+                        );
+                        header_width += options.labels.width();
+                    }
+                    Column::Time => {
+                        put!("{}", datetime.fg::<Blue>().bold())?;
+                        header_width += display_width(&datetime);
                     }
-                    _ => {
-                        put!(" {}", logline.message)?;
+                    Column::Elapsed => {
+                        let elapsed = logline.elapsed.unwrap_or_default();
+                        let text = format!(
+                            "{}{:.3}s",
+                            if elapsed < TimeDelta::zero() {
+                                "-"
+                            } else {
+                                "+"
+                            },
+                            (elapsed.abs().num_milliseconds() as f64) / 1000.0
+                        );
+
+                        if elapsed > options.elapsed_threshold {
+                            put!("{}", text.fg::<WarningColor>().bold())?;
+                        } else {
+                            put!("{}", text.dimmed())?;
+                        }
+                        header_width += display_width(&text);
                     }
+                    Column::Logger => {
+                        match logger_color {
+                            Some(color) => put!("{}", logline.logger_name.color(color).bold())?,
+                            None => put!("{}", logline.logger_name.fg::<WarningColor>().bold())?,
+                        }
+                        header_width += display_width(&logline.logger_name);
+
+                        if let Some(source) = &logline.source {
+                            put!(" {}", format!("[{}]", source).fg::<Cyan>())?;
+                            header_width += display_width(source) + 3;
+                        }
+                    }
+                    Column::Thread => {
+                        put!("{}", format!("[{}]", logline.thread).dimmed())?;
+                        header_width += display_width(&logline.thread) + 2;
+                    }
+                    Column::Message => {
+                        let message = compact(&logline.message, options);
+
+                        if i > 0 {
+                            put!(":")?;
+                            header_width += 1;
+                        }
+
+                        // Never the case with "--compact"
+                        if message.contains('\n') {
+                            boxed = Some(match options.box_wrap_width {
+                                Some(width) => Cow::Owned(hard_wrap(
+                                    &logline.message,
+                                    width.saturating_sub(options.box_indent + 2).max(1),
+                                )),
+                                None => Cow::Borrowed(&logline.message),
+                            });
+                            continue;
+                        }
+
+                        match options.wrap_width {
+                            Some(width) if header_width + 1 + display_width(&message) > width => {
+                                // The indentation plus the glyph and the space after it
+                                let box_width = options.box_indent + 2;
+                                boxed = Some(Cow::Owned(hard_wrap(
+                                    &message,
+                                    width.saturating_sub(box_width).max(1),
+                                )));
+                                continue;
+                            }
+                            _ => {}
+                        }
+
+                        // Single-line message
+                        if i > 0 {
+                            put!(" ")?;
+                        }
+                        if color_message {
+                            putc!(highlight(&message));
+                        } else {
+                            put!("{}", highlight(&message))?;
+                        }
+                    }
+                }
+            }
+
+            if let Some(message) = boxed {
+                let line_count = message.lines().count();
+                let (glyph_middle, glyph_last) = match options.ascii {
+                    true => ("| ", "\\ "),
+                    false => ("│ ", "╰ "),
                 };
-            } else {
-                let line_count = logline.message.lines().count();
+                let indent = " ".repeat(options.box_indent);
 
                 // Multi-line log message, we draw a little box around it
-                for (i, line) in logline.message.lines().enumerate() {
+                for (i, line) in message.lines().enumerate() {
                     put!("\n")?;
 
                     if i < (line_count - 1) {
-                        putc!("   │ ");
+                        putc!(format!("{}{}", indent, glyph_middle));
                     } else {
-                        putc!("   ╰ ");
+                        putc!(format!("{}{}", indent, glyph_last));
                     }
 
-                    if matches!(logline.severity, Severity::Error | Severity::Critical) {
-                        putc!(line);
+                    if color_message {
+                        putc!(highlight(line));
                     } else {
-                        put!("{}", line)?;
+                        put!("{}", highlight(line))?;
                     }
                 }
             }
@@ -106,3 +592,611 @@ pub fn print_logline(
 
     Ok(())
 }
+
+/// Gives every match of each pattern a background color, the first pattern wins where matches
+/// overlap
+///
+/// Only the background is changed, so the color of the text around a match is left intact.
+/// Nothing is highlighted without `use_color`.
+///
+fn highlight_patterns<'a>(line: &'a str, options: &FormatOptions) -> Cow<'a, str> {
+    if !options.use_color {
+        return Cow::Borrowed(line);
+    }
+
+    let mut matches: Vec<(usize, usize, AnsiColors)> = options
+        .highlights
+        .iter()
+        .zip(HIGHLIGHT_COLORS.iter().cycle())
+        .flat_map(|(pattern, &color)| {
+            pattern
+                .find_iter(line)
+                .filter(|m| !m.is_empty())
+                .map(move |m| (m.start(), m.end(), color))
+        })
+        .collect();
+
+    if matches.is_empty() {
+        return Cow::Borrowed(line);
+    }
+
+    // Stable, so the first pattern comes first among matches starting at the same place
+    matches.sort_by_key(|&(start, _, _)| start);
+
+    let mut highlighted = String::with_capacity(line.len());
+    let mut end_of_last = 0;
+    for (start, end, color) in matches {
+        if start < end_of_last {
+            continue;
+        }
+        highlighted.push_str(&line[end_of_last..start]);
+        let matched = &line[start..end];
+        write!(highlighted, "{}", matched.on_color(color)).unwrap();
+        end_of_last = end;
+    }
+    highlighted.push_str(&line[end_of_last..]);
+
+    Cow::Owned(highlighted)
+}
+
+/// Prints a line as-is for "--raw-tail", colored by the severity at the start of it if it has one
+pub fn print_raw_tail_line(line: &str, target: &mut impl Write) -> std::io::Result<()> {
+    match line_severity(line) {
+        Some(Severity::Trace) => writeln!(target, "{}", line.fg::<DebugColor>().dimmed()),
+        Some(Severity::Debug) => writeln!(target, "{}", line.fg::<DebugColor>()),
+        Some(Severity::Info) => writeln!(target, "{}", line.fg::<InfoColor>()),
+        Some(Severity::Warning) => writeln!(target, "{}", line.fg::<WarningColor>()),
+        Some(Severity::Error | Severity::Critical) => {
+            writeln!(target, "{}", line.fg::<ErrorColor>())
+        }
+        Some(Severity::Unknown(_)) | None => writeln!(target, "{}", line),
+    }
+}
+
+/// Prints raw input lines, as shown around log messages with "--context-lines-raw"
+pub fn print_raw_lines(
+    lines: &[String],
+    target: &mut impl Write,
+    options: &FormatOptions,
+) -> std::io::Result<()> {
+    let glyph = match options.ascii {
+        true => "  : ",
+        false => "  ┊ ",
+    };
+
+    for line in lines {
+        writeln!(target, "{}", format!("{}{}", glyph, line).dimmed())?;
+    }
+
+    Ok(())
+}
+
+/// Separates one log message and its raw context from the next, like "grep -C"
+pub fn print_raw_separator(target: &mut impl Write) -> std::io::Result<()> {
+    writeln!(target, "{}", "--".dimmed())
+}
+
+/// Prints a header introducing a group of log lines
+pub fn print_group_header(
+    name: &str,
+    target: &mut impl Write,
+    options: &FormatOptions,
+) -> std::io::Result<()> {
+    let header = match options.ascii {
+        true => format!("-- {} --", name),
+        false => format!("── {} ──", name),
+    };
+
+    writeln!(target, "{}", header.fg::<WarningColor>().bold())
+}
+
+/// Prints just the message of a log line, without any of the NSO framing
+fn print_message_only(
+    logline: &LogLine,
+    target: &mut impl Write,
+    options: &FormatOptions,
+) -> std::io::Result<()> {
+    let (text, severity) = match logline {
+        LogLine::Normal(logline) => (&logline.message, Some(&logline.severity)),
+        LogLine::Dangling(logline) => (&logline.text, None),
+    };
+    let text = compact(text, options);
+
+    // Colored line by line, so each line of a multi-line message stands on its own in a pager
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            writeln!(target)?;
+        }
+
+        let line = highlight_patterns(line, options);
+        if !options.use_color {
+            write!(target, "{}", line)?;
+            continue;
+        }
+
+        match severity {
+            Some(Severity::Trace) => write!(target, "{}", line.fg::<DebugColor>().dimmed())?,
+            Some(Severity::Debug) => write!(target, "{}", line.fg::<DebugColor>())?,
+            Some(Severity::Info) => write!(target, "{}", line.fg::<InfoColor>())?,
+            Some(Severity::Warning) => write!(target, "{}", line.fg::<WarningColor>())?,
+            Some(Severity::Error | Severity::Critical) => {
+                write!(target, "{}", line.fg::<ErrorColor>())?
+            }
+            Some(Severity::Unknown(_)) | None => write!(target, "{}", line)?,
+        }
+    }
+    writeln!(target)?;
+
+    Ok(())
+}
+
+/// Prints a log line as JSON, as a single line unless `json_pretty` is set
+pub fn print_logline_json(
+    logline: &LogLine,
+    target: &mut impl Write,
+    options: &FormatOptions,
+) -> std::io::Result<()> {
+    let logline = JsonLogLine {
+        schema_version: JSON_SCHEMA_VERSION,
+        logline,
+    };
+
+    match options.json_pretty {
+        true => serde_json::to_writer_pretty(&mut *target, &logline)?,
+        false => serde_json::to_writer(&mut *target, &logline)?,
+    }
+    writeln!(target)?;
+
+    Ok(())
+}
+
+/// Prints the header row of CSV output
+pub fn print_csv_header(target: &mut impl Write, options: &FormatOptions) -> std::io::Result<()> {
+    let mut writer = csv_writer(target, options);
+    writer.write_record(CSV_HEADER)?;
+    // Dropping the writer would flush it too, but ignore any errors
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Prints a log line as a row of CSV, dangling lines only fill in the message
+pub fn print_logline_csv(
+    logline: &LogLine,
+    target: &mut impl Write,
+    options: &FormatOptions,
+) -> std::io::Result<()> {
+    let mut writer = csv_writer(target, options);
+
+    match logline {
+        LogLine::Normal(logline) => writer.write_record([
+            &logline
+                .datetime
+                .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            &logline.severity.to_string(),
+            &logline.logger_name,
+            &logline.thread,
+            &logline.message,
+        ])?,
+        LogLine::Dangling(dangling) => writer.write_record(["", "", "", "", &dangling.text])?,
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+const CSV_HEADER: [&str; 5] = ["datetime", "severity", "logger", "thread", "message"];
+
+fn csv_writer<W: Write>(target: W, options: &FormatOptions) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .delimiter(options.field_separator.byte())
+        .has_headers(false)
+        .from_writer(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ansi::strip_ansi;
+    use crate::parser::{parse_str, LineFormat, MessageDelimiter};
+
+    fn options() -> FormatOptions {
+        FormatOptions {
+            dateformat: DateFormat::Full,
+            timezone: Timezone::Utc,
+            labels: LabelStyle::Short,
+            ascii: false,
+            wrap_width: None,
+            box_wrap_width: None,
+            columns: DEFAULT_COLUMNS.to_vec(),
+            box_indent: 3,
+            elapsed_threshold: TimeDelta::milliseconds(1000),
+            highlights: Vec::new(),
+            prefix_file: false,
+            json_pretty: false,
+            field_separator: FieldSeparator::Comma,
+            color_by: ColorBy::Severity,
+            mark_unparsed: false,
+            compact: None,
+            message_only: false,
+            use_color: false,
+        }
+    }
+
+    fn parse(text: &str) -> LogLine {
+        parse_str(text, LineFormat::Nso(MessageDelimiter::Either))
+            .next()
+            .unwrap()
+    }
+
+    fn pretty(logline: &LogLine, options: &FormatOptions) -> String {
+        let mut output = Vec::new();
+        print_logline(logline, &mut output, options).unwrap();
+        strip_ansi(&String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn time_only() {
+        let logline =
+            parse("<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Starting up\n");
+        let options = FormatOptions {
+            dateformat: DateFormat::TimeOnly,
+            ..options()
+        };
+
+        assert_eq!(
+            pretty(&logline, &options),
+            "INFO 15:04:05.123 my-logger [MainThread]: Starting up\n"
+        );
+    }
+
+    #[test]
+    fn relative_timestamps() {
+        let options = FormatOptions {
+            dateformat: DateFormat::Relative(Cell::new(None)),
+            ..options()
+        };
+        let relative = |line: &str| {
+            let rendered = pretty(&parse(line), &options);
+            rendered.split(' ').nth(1).unwrap().to_string()
+        };
+
+        // The first log message shown is the start
+        assert_eq!(
+            relative("<INFO> 02-Jan-2024::15:04:05.123 a b: - First\n"),
+            "+00:00:00.000"
+        );
+        assert_eq!(
+            relative("<INFO> 02-Jan-2024::16:05:06.124 a b: - Later\n"),
+            "+01:01:01.001"
+        );
+        // Hours aren't wrapped at a day, and an earlier message is before the start
+        assert_eq!(
+            relative("<INFO> 04-Jan-2024::15:04:05.123 a b: - Days later\n"),
+            "+48:00:00.000"
+        );
+        assert_eq!(
+            relative("<INFO> 02-Jan-2024::15:04:04.623 a b: - Out of order\n"),
+            "-00:00:00.500"
+        );
+    }
+
+    fn raw(logline: &LogLine, options: &FormatOptions) -> String {
+        let mut output = Vec::new();
+        print_logline(logline, &mut output, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn highlights_need_color() {
+        let logline = parse("<INFO> 02-Jan-2024::15:04:05.123 a b: - Found the needle here\n");
+        let options = |use_color| FormatOptions {
+            highlights: vec![Regex::new("needle").unwrap()],
+            message_only: true,
+            use_color,
+            ..options()
+        };
+
+        let colored = raw(&logline, &options(true));
+        assert_ne!(colored, strip_ansi(&colored));
+        assert_eq!(strip_ansi(&colored), "Found the needle here\n");
+        assert_eq!(raw(&logline, &options(false)), "Found the needle here\n");
+
+        // The rest of the pretty output is always colored, the highlight isn't
+        let pretty = |use_color| {
+            raw(
+                &logline,
+                &FormatOptions {
+                    message_only: false,
+                    ..options(use_color)
+                },
+            )
+        };
+        assert!(pretty(true).len() > pretty(false).len());
+        assert_eq!(strip_ansi(&pretty(true)), strip_ansi(&pretty(false)));
+    }
+
+    fn csv(logline: &LogLine, options: &FormatOptions) -> String {
+        let mut output = Vec::new();
+        print_logline_csv(logline, &mut output, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv_quotes_commas_and_quotes() {
+        let logline =
+            parse("<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - a, \"b\" and c\n");
+
+        assert_eq!(
+            csv(&logline, &options()),
+            "2024-01-02T15:04:05.123Z,INFO,my-logger,MainThread,\"a, \"\"b\"\" and c\"\n"
+        );
+    }
+
+    #[test]
+    fn csv_quotes_multi_line_messages() {
+        let logline =
+            parse("<ERROR> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Boom\n  line 2\n");
+
+        assert_eq!(
+            csv(&logline, &options()),
+            "2024-01-02T15:04:05.123Z,ERROR,my-logger,MainThread,\"Boom\n  line 2\"\n"
+        );
+    }
+
+    #[test]
+    fn csv_with_tabs() {
+        let logline = parse("<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - a, b\n");
+        let options = FormatOptions {
+            field_separator: FieldSeparator::Tab,
+            ..options()
+        };
+
+        assert_eq!(
+            csv(&logline, &options),
+            "2024-01-02T15:04:05.123Z\tINFO\tmy-logger\tMainThread\ta, b\n"
+        );
+    }
+
+    /// A writer that fails every write, like a closed pipe
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn csv_reports_write_errors() {
+        let logline = parse("<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Hi\n");
+
+        assert!(print_csv_header(&mut BrokenPipe, &options()).is_err());
+        assert!(print_logline_csv(&logline, &mut BrokenPipe, &options()).is_err());
+    }
+
+    const MULTI_LINE: &str =
+        "<ERROR> 02-Jan-2024::15:04:05.123 my-logger MainThread: - a, \"b\"\n  c\n";
+
+    #[test]
+    fn json_round_trip() {
+        let logline = parse(MULTI_LINE);
+        let mut output = Vec::new();
+        print_logline_json(&logline, &mut output, &options()).unwrap();
+        let reparsed = serde_json::from_slice(&output).unwrap();
+
+        let (LogLine::Normal(logline), LogLine::Normal(reparsed)) = (logline, reparsed) else {
+            panic!("Not a log message");
+        };
+        assert_eq!(reparsed.severity, logline.severity);
+        assert_eq!(reparsed.datetime, logline.datetime);
+        assert_eq!(reparsed.logger_name, logline.logger_name);
+        assert_eq!(reparsed.thread, logline.thread);
+        assert_eq!(reparsed.message, logline.message);
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let logline = parse(MULTI_LINE);
+        let output = csv(&logline, &options());
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(output.as_bytes());
+        let record = reader.records().next().unwrap().unwrap();
+        assert_eq!(
+            record.iter().collect::<Vec<_>>(),
+            [
+                "2024-01-02T15:04:05.123Z",
+                "ERROR",
+                "my-logger",
+                "MainThread",
+                "a, \"b\"\n  c"
+            ]
+        );
+    }
+
+    const EVERY_SEVERITY: &str = "\
+<TRACE> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Tracing
+<DEBUG> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Debugging
+<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Informing
+<WARNING> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Warning
+<ERROR> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Failing
+Traceback line 1
+  line 2
+<CRITICAL> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Failing badly
+";
+
+    #[test]
+    fn ascii_output_is_ascii() {
+        for labels in [LabelStyle::Short, LabelStyle::Long, LabelStyle::Icons] {
+            let options = FormatOptions {
+                ascii: true,
+                labels,
+                ..options()
+            };
+
+            for logline in parse_str(EVERY_SEVERITY, LineFormat::Nso(MessageDelimiter::Either)) {
+                let output = pretty(&logline, &options);
+                assert!(output.is_ascii(), "{:?}", output);
+            }
+        }
+    }
+
+    #[test]
+    fn ascii_box() {
+        let logline =
+            parse("<ERROR> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Boom\n  line 2\n");
+        let options = FormatOptions {
+            ascii: true,
+            ..options()
+        };
+
+        assert_eq!(
+            pretty(&logline, &options),
+            " ERR 2024-01-02 15:04:05.123 my-logger [MainThread]:\n   | Boom\n   \\   line 2\n"
+        );
+    }
+
+    #[test]
+    fn long_labels() {
+        let options = FormatOptions {
+            labels: LabelStyle::Long,
+            ..options()
+        };
+        let labels: Vec<String> =
+            parse_str(EVERY_SEVERITY, LineFormat::Nso(MessageDelimiter::Either))
+                .map(|logline| pretty(&logline, &options)[..8].to_string())
+                .collect();
+
+        assert_eq!(
+            labels,
+            ["   TRACE", "   DEBUG", "    INFO", " WARNING", "   ERROR", "CRITICAL"]
+        );
+    }
+
+    fn json(logline: &LogLine, options: &FormatOptions) -> String {
+        let mut output = Vec::new();
+        print_logline_json(logline, &mut output, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn keys(json: &str) -> Vec<String> {
+        let object: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(json).unwrap();
+        object.keys().cloned().collect()
+    }
+
+    #[test]
+    fn json_fields() {
+        let output = json(&parse(MULTI_LINE), &options());
+
+        assert_eq!(
+            keys(&output),
+            [
+                "datetime",
+                "logger_name",
+                "message",
+                "schema_version",
+                "severity",
+                "thread"
+            ]
+        );
+        assert!(output.contains(r#""schema_version":1"#));
+        assert!(output.contains(r#""datetime":"2024-01-02T15:04:05.123Z""#));
+    }
+
+    #[test]
+    fn json_fields_of_dangling_lines() {
+        let output = json(&parse("not a log message\n"), &options());
+
+        assert_eq!(keys(&output), ["schema_version", "text"]);
+    }
+
+    #[test]
+    fn compact_json_is_one_line_per_log_line() {
+        for logline in parse_str(EVERY_SEVERITY, LineFormat::Nso(MessageDelimiter::Either)) {
+            let output = json(&logline, &options());
+
+            assert!(output.ends_with('\n'));
+            assert_eq!(output.lines().count(), 1, "{}", output);
+        }
+    }
+
+    #[test]
+    fn pretty_json() {
+        let options = FormatOptions {
+            json_pretty: true,
+            ..options()
+        };
+        let output = json(&parse(MULTI_LINE), &options);
+
+        assert!(output.lines().count() > 1);
+        assert_eq!(keys(&output).len(), 6);
+    }
+
+    fn filename_timezone(filename: &str) -> Option<String> {
+        timezone_from_filename(filename).map(|timezone| match timezone {
+            Timezone::Fixed(offset) => offset.to_string(),
+            other => format!("{:?}", other),
+        })
+    }
+
+    #[test]
+    fn timezones_in_filenames() {
+        for (filename, timezone) in [
+            ("ncs-java-vm.utc.log", "Utc"),
+            ("UTC_ncs.log", "Utc"),
+            ("ncs-gmt", "Utc"),
+            ("ncs.utc+2.log", "+02:00"),
+            ("ncs.utc+0530.log", "+05:30"),
+            ("ncs-GMT-05:30.log", "-05:30"),
+            ("ncs-utc-3:30_old.log", "-03:30"),
+            ("ncs.utc+14.log", "+14:00"),
+            ("ncs.utc-00:59.log", "-00:59"),
+        ] {
+            assert_eq!(
+                filename_timezone(filename).as_deref(),
+                Some(timezone),
+                "{}",
+                filename
+            );
+        }
+    }
+
+    #[test]
+    fn dates_after_utc_are_not_offsets() {
+        for filename in [
+            "ncs-utc-2024-01-02.log",
+            "ncs-utc-20240102.log",
+            "ncs-utc-123.log",
+            "ncs-utc-1:30:00.log",
+        ] {
+            assert_eq!(
+                filename_timezone(filename).as_deref(),
+                Some("Utc"),
+                "{}",
+                filename
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_filename_timezones() {
+        for filename in [
+            "ncs-python-vm.log",
+            "ncs.utc+15.log",
+            "ncs.utc+05:60.log",
+            "ncs.utc+0575.log",
+            "ncs.utc+2x.log",
+            "ncs.utcfoo.log",
+            "ncs.computc.log",
+        ] {
+            assert_eq!(filename_timezone(filename), None, "{}", filename);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::Write;
+
+use chrono::{Local, Timelike};
+use owo_colors::OwoColorize;
+
+use crate::ansi::display_width;
+use crate::formatting::Timezone;
+use crate::parser::{LogLine, Severity};
+
+/// The widest a bar can be
+const MAX_BAR_WIDTH: usize = 50;
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum CountBy {
+    Severity,
+    Logger,
+    /// Hour of the day, in the display timezone
+    Hour,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Bucket {
+    Severity(Severity),
+    Logger(String),
+    Hour(u32),
+}
+
+impl Display for Bucket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bucket::Severity(severity) => write!(f, "{}", severity),
+            Bucket::Logger(logger_name) => write!(f, "{}", logger_name),
+            Bucket::Hour(hour) => write!(f, "{:02}:00", hour),
+        }
+    }
+}
+
+/// Counts log messages by some dimension, for "--count-by"
+///
+/// Dangling lines aren't counted, since they have none of the fields we count by.
+///
+pub struct Histogram {
+    count_by: CountBy,
+    timezone: Timezone,
+    counts: BTreeMap<Bucket, usize>,
+}
+
+impl Histogram {
+    pub fn new(count_by: CountBy, timezone: Timezone) -> Self {
+        Self {
+            count_by,
+            timezone,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, logline: &LogLine) {
+        let LogLine::Normal(logline) = logline else {
+            return;
+        };
+
+        let bucket = match self.count_by {
+            CountBy::Severity => Bucket::Severity(logline.severity.clone()),
+            CountBy::Logger => Bucket::Logger(logline.logger_name.clone()),
+            CountBy::Hour => Bucket::Hour(match self.timezone {
+                Timezone::Utc => logline.datetime.hour(),
+                Timezone::Local => logline.datetime.with_timezone(&Local).hour(),
+                Timezone::Fixed(offset) => logline.datetime.with_timezone(&offset).hour(),
+            }),
+        };
+
+        *self.counts.entry(bucket).or_default() += 1;
+    }
+
+    /// Prints one aligned row per bucket, with a bar scaled to the largest count
+    pub fn print(&self, target: &mut impl Write, ascii: bool) -> std::io::Result<()> {
+        let labels: Vec<String> = self
+            .counts
+            .keys()
+            .map(|bucket| bucket.to_string())
+            .collect();
+        let label_width = labels.iter().map(|label| display_width(label)).max();
+        let max_count = self.counts.values().copied().max().unwrap_or(0);
+        let count_width = max_count.to_string().len();
+        let glyph = if ascii { "#" } else { "█" };
+
+        for (label, count) in labels.iter().zip(self.counts.values()) {
+            // Always show at least a sliver, so non-zero counts don't look empty
+            let bar_width = (count * MAX_BAR_WIDTH).div_ceil(max_count);
+
+            // Padded by hand, "{:<N}" counts characters, not the columns wide ones take up
+            let padding = label_width.unwrap_or(0) - display_width(label);
+
+            writeln!(
+                target,
+                "{}{} {:>count_width$} {}",
+                label,
+                " ".repeat(padding),
+                count,
+                glyph.repeat(bar_width).blue(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ansi::strip_ansi;
+    use crate::parser::{parse_str, LineFormat, MessageDelimiter};
+
+    const LOG: &str = "\
+<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - One
+<INFO> 02-Jan-2024::15:30:00.000 my-logger MainThread: - Two
+  still two
+<ERROR> 02-Jan-2024::16:00:00.000 other MainThread: - Three
+<INFO> 02-Jan-2024::23:59:59.999 my-logger MainThread: - Four
+";
+
+    fn histogram(log: &str, count_by: CountBy, timezone: Timezone) -> Vec<String> {
+        let mut histogram = Histogram::new(count_by, timezone);
+        for logline in parse_str(log, LineFormat::Nso(MessageDelimiter::Either)) {
+            histogram.add(&logline);
+        }
+
+        let mut output = Vec::new();
+        histogram.print(&mut output, true).unwrap();
+        strip_ansi(&String::from_utf8(output).unwrap())
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    fn bar(width: usize) -> String {
+        "#".repeat(width)
+    }
+
+    #[test]
+    fn count_by_severity() {
+        assert_eq!(
+            histogram(LOG, CountBy::Severity, Timezone::Utc),
+            [
+                format!("INFO  3 {}", bar(50)),
+                format!("ERROR 1 {}", bar(17))
+            ]
+        );
+    }
+
+    #[test]
+    fn count_by_logger() {
+        assert_eq!(
+            histogram(LOG, CountBy::Logger, Timezone::Utc),
+            [
+                format!("my-logger 3 {}", bar(50)),
+                format!("other     1 {}", bar(17)),
+            ]
+        );
+    }
+
+    #[test]
+    fn count_by_hour() {
+        let plus_one = Timezone::Fixed(chrono::FixedOffset::east_opt(3600).unwrap());
+
+        assert_eq!(
+            histogram(LOG, CountBy::Hour, Timezone::Utc),
+            [
+                format!("15:00 2 {}", bar(50)),
+                format!("16:00 1 {}", bar(25)),
+                format!("23:00 1 {}", bar(25)),
+            ]
+        );
+        // The last message is past midnight in UTC+1
+        assert_eq!(
+            histogram(LOG, CountBy::Hour, plus_one),
+            [
+                format!("00:00 1 {}", bar(25)),
+                format!("16:00 2 {}", bar(50)),
+                format!("17:00 1 {}", bar(25)),
+            ]
+        );
+    }
+
+    #[test]
+    fn bars_are_scaled_to_the_largest_count() {
+        let mut log = String::new();
+        for _ in 0..200 {
+            log.push_str("<INFO> 02-Jan-2024::15:04:05.123 a b: - Info\n");
+        }
+        log.push_str("<ERROR> 02-Jan-2024::15:04:05.123 a b: - Error\n");
+
+        // A single message still gets a sliver of a bar
+        assert_eq!(
+            histogram(&log, CountBy::Severity, Timezone::Utc),
+            [
+                format!("INFO  200 {}", bar(50)),
+                format!("ERROR   1 {}", bar(1))
+            ]
+        );
+    }
+
+    #[test]
+    fn wide_labels_are_aligned() {
+        let log = "\
+<INFO> 02-Jan-2024::15:04:05.123 日本 a: - Wide
+<INFO> 02-Jan-2024::15:04:05.123 abc a: - Narrow
+";
+
+        assert_eq!(
+            histogram(log, CountBy::Logger, Timezone::Utc),
+            [format!("abc  1 {}", bar(50)), format!("日本 1 {}", bar(50))]
+        );
+    }
+
+    #[test]
+    fn dangling_lines_are_not_counted() {
+        assert!(histogram("just\nsome text\n", CountBy::Severity, Timezone::Utc).is_empty());
+    }
+}
@@ -1,64 +1,658 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::File;
-use std::io::{stdin, Write};
-use std::path::Path;
+use std::io::{stdin, BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::{Duration, Instant};
 
+use chrono::{TimeDelta, Utc};
 use clap::{CommandFactory, Parser};
+use regex::Regex;
 use subprocess::Exec;
 
+mod ansi;
+use ansi::strip_ansi;
+#[cfg(feature = "builtin-pager")]
+mod builtin_pager;
+mod editor;
+use editor::edit;
+mod fields;
+use fields::extract_fields;
+mod filtering;
+use filtering::{
+    parse_duration, parse_timestamp, start_of_day, GrepFilter, SkipPartial, TimeFilter, TimeRange,
+};
+mod follow_many;
+use follow_many::follow_many;
 mod formatting;
-use formatting::{print_logline, DateFormat};
+use formatting::{
+    print_csv_header, print_group_header, print_logline, print_logline_csv, print_logline_json,
+    print_raw_lines, print_raw_separator, print_raw_tail_line, timezone_from_filename,
+    validate_strftime, ColorBy, Column, DateFormat, FieldSeparator, FormatOptions, LabelStyle,
+    OutputFormat, TimestampMode, Timezone, DEFAULT_BOX_INDENT, DEFAULT_COLUMNS,
+};
+mod histogram;
+use histogram::{CountBy, Histogram};
 mod parser;
-use parser::{parse_log, ParseSource};
+use parser::{
+    format_help, parse_jsonl, parse_log, parse_raw, parse_str, HeaderCheck, InputFormat,
+    LineFormat, LogLine, MessageDelimiter, ParseSource, READ_TIMEOUT,
+};
 mod pattern_matching;
-use pattern_matching::match_pattern;
+use pattern_matching::{
+    logs_dir, match_pattern, preferred_match, sort_matches, Prefer, SortBy, DEFAULT_GLOB,
+};
+mod progress;
+use progress::Progress;
+mod signals;
 mod tail;
 use tail::tail;
+mod tee;
+use tee::{open_tee_file, Tee};
 
 const HELP_TEXT: &str = "
     Input one or more patterns to match a log file to read. The selected log file has to match
     every pattern you input. If multiple log files match, the one with the shortest name will be
-    selected.
+    selected, unless told otherwise with \"--prefer\".
+
+    Without patterns or \"-F\", STDIN is read. If STDIN is a terminal, the \"--default-log\"
+    is opened if one is configured, otherwise this help is printed.
 
     Example:
 
     $ nso-log-reader cfs l3vpn
+
+    Exit codes: 0 on success, 1 if a filter like \"--since\" was used but nothing matched, 2 on
+    errors, 3 if \"--check\" found too many lines it couldn't parse, 128 plus the signal number
+    when interrupted.
 ";
 
+/// How many lines to look at before deciding whether the input looks like an NSO log
+const NOT_NSO_SAMPLE_SIZE: usize = 20;
+/// The share of unparseable lines in the sample at which the input doesn't look like an NSO log
+const NOT_NSO_DANGLING_RATIO: f64 = 0.5;
+
+/// How many lines of history to start with when following
+const DEFAULT_FOLLOW_LINES: usize = 100;
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_NO_MATCHES: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+const EXIT_CHECK_FAILED: i32 = 3;
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum GroupBy {
+    Logger,
+}
+
 #[derive(Debug, Parser)]
 #[clap(about = HELP_TEXT)]
 struct Args {
+    /// Check that the last lines of the log can be parsed and exit, for CI and monitoring. Prints
+    /// a one-line summary, exits with 3 if too many lines couldn't be parsed.
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "follow",
+            "raw_tail",
+            "follow_in_pager",
+            "edit",
+            "print_path",
+            "passthrough"
+        ]
+    )]
+    check: bool,
+
+    /// How many lines at the end of the log "--check" looks at
+    #[clap(
+        long,
+        value_name = "N",
+        default_value_t = NonZeroUsize::new(1000).unwrap(),
+        requires = "check"
+    )]
+    check_lines: NonZeroUsize,
+
+    /// The share of dangling lines, from 0 to 1, above which "--check" fails
+    #[clap(
+        long,
+        value_name = "RATIO",
+        default_value_t = NOT_NSO_DANGLING_RATIO,
+        requires = "check"
+    )]
+    max_dangling_ratio: f64,
+
+    /// Describe the log line format that's expected and exit, e.g. to find out why lines end up
+    /// as dangling lines
+    #[clap(long, visible_alias = "explain-format")]
+    help_format: bool,
+
     /// Read a NSO log file by matching substrings
     #[clap(value_parser)]
     patterns: Vec<String>,
 
-    /// The path to a log file to parse
+    /// The path to a log file to parse, or "-" to read from STDIN. A leading "~" and environment
+    /// variables such as "$NSO_RUN_DIR" are expanded.
     #[clap(short = 'F', long, value_parser = file_exists)]
     logfile: Option<String>,
 
+    /// The NSO logs directory, where patterns are matched [default: $NSO_RUN_DIR/logs]
+    #[clap(long, value_name = "DIR", env = "NSO_LOG_DIR")]
+    log_dir: Option<PathBuf>,
+
+    /// The log file to open when run without patterns or "-F" and STDIN is a terminal, instead
+    /// of printing this help. A file name is looked up in the logs directory, e.g.
+    /// "ncs-python-vm.log", anything with a "/" in it is used as a path.
+    #[clap(long, value_name = "FILE", env = "NSO_LOG_READER_DEFAULT_LOG")]
+    default_log: Option<String>,
+
+    /// When several files are read, warn about files that can't be opened and carry on with the
+    /// others, rather than stopping. Still exits with an error if anything was skipped.
+    #[clap(long)]
+    keep_going: bool,
+
+    /// Show each log message as soon as its first line is read. Normally the parser waits briefly
+    /// (10 ms) for more lines that may belong to the message, with this only lines that arrive
+    /// together are grouped, so a message that's written in pieces may be split.
+    #[clap(long)]
+    no_lookahead: bool,
+
     /// Tail the file rather than paging it
     #[clap(short, long)]
     follow: bool,
 
+    /// When following, start with the last N lines of the file [default: 100]. With 0, only lines
+    /// written from now on are shown, starting with the first complete log message.
+    #[clap(short = 'n', long, value_name = "N")]
+    lines: Option<usize>,
+
+    /// Page the whole file, then keep following it in the pager, like "less +F". Press Ctrl+C to
+    /// stop following and scroll. (Can't be combined with "-c", which turns off the pager)
+    #[clap(long, conflicts_with_all = ["follow", "cat"])]
+    follow_in_pager: bool,
+
+    /// Open the log file in an editor at its last line, rather than reading it. This skips all
+    /// parsing and formatting, the editor gets the file as-is.
+    #[clap(long, conflicts_with_all = ["follow", "follow_in_pager", "cat", "all"])]
+    edit: bool,
+
+    /// The editor to open with "--edit", e.g. "code -w" [default: $VISUAL, $EDITOR or "vi"]
+    #[clap(long, value_name = "COMMAND")]
+    editor: Option<String>,
+
+    /// How often "tail" checks the file for changes while following, in milliseconds. Only
+    /// matters when tail has to poll (e.g. on network filesystems), otherwise it's notified of
+    /// changes right away. Unrelated to how long the parser waits for the rest of a message.
+    #[clap(
+        long,
+        value_name = "MS",
+        default_value_t = 250,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    poll_interval: u64,
+
+    /// Follow every file matched by the patterns at once, rather than just the best match
+    #[clap(long, requires = "follow", conflicts_with_all = ["logfile", "select"])]
+    all: bool,
+
+    /// Prefix each log message with the name of its file, like "grep -H". This is the default
+    /// when following several files.
+    #[clap(
+        short = 'H',
+        long,
+        visible_alias = "with-filename",
+        overrides_with = "no_prefix_source"
+    )]
+    prefix_source: bool,
+
+    /// Don't prefix log messages with the name of their file, even when following several files
+    #[clap(long, overrides_with = "prefix_source")]
+    no_prefix_source: bool,
+
+    /// Start at the top of the log in the pager, rather than at the end
+    #[clap(
+        long,
+        visible_alias = "no-jump-to-end",
+        conflicts_with_all = ["follow", "follow_in_pager", "cat"]
+    )]
+    top: bool,
+
+    /// Open the pager at the first log message matching this regex, rather than at the end. "less"
+    /// does the searching, so its regex syntax applies.
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        value_parser = Regex::new,
+        conflicts_with_all = ["follow", "follow_in_pager", "cat", "top"]
+    )]
+    jump_to: Option<Regex>,
+
     /// Print the entire file rather than paging it
     #[clap(short, long)]
     cat: bool,
 
+    /// Flush the output after every log message, even when it's a pipe, like "grep
+    /// --line-buffered". Implied when following or reading from a pipe.
+    #[clap(long, visible_alias = "line-buffered")]
+    unbuffered: bool,
+
+    /// Also write the output to FILE, without colors, while still paging or printing it
+    #[clap(long, value_name = "FILE")]
+    tee: Option<PathBuf>,
+
+    /// Keep the colors in the copy written by "--tee"
+    #[clap(long, requires = "tee")]
+    tee_colors: bool,
+
+    /// Print how far into the file we've read to STDERR, for very large files
+    #[clap(long, conflicts_with_all = ["follow", "follow_in_pager"])]
+    progress: bool,
+
     /// Show only the time, not the full date (implied when using "-f")
     #[clap(short, long)]
     time: bool,
 
-    /// Print matches and exit, useful for troubleshooting
+    /// Format timestamps using a custom strftime string, e.g. "%H:%M:%S" (overrides "-t")
+    #[clap(long, value_parser = valid_timefmt)]
+    timefmt: Option<String>,
+
+    /// Show timestamps as the time of each log message, or as the time since the first one shown,
+    /// like "dmesg". With several files, the first log message shown from any of them is the
+    /// start. (Can't be combined with "--timefmt" or "-t")
+    #[clap(
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value_t = TimestampMode::Absolute,
+        conflicts_with_all = ["timefmt", "time"]
+    )]
+    timestamps: TimestampMode,
+
+    /// Display timestamps in UTC, as they are written in the log
+    #[clap(long, conflicts_with = "local")]
+    utc: bool,
+
+    /// Display timestamps in local time (default)
+    #[clap(long)]
+    local: bool,
+
+    /// Display timestamps in the timezone in the file name, if there is one, e.g. "utc" or
+    /// "UTC+0530" separated by ".", "-" or "_". Best-effort, other names are ignored.
+    #[clap(long)]
+    tz_from_filename: bool,
+
+    /// How to output the parsed log
+    #[clap(long, value_enum, default_value_t = OutputFormat::Pretty)]
+    format: OutputFormat,
+
+    /// With "--format json", include the lines each log message was parsed from, exactly as they
+    /// were read, as "raw"
+    #[clap(long)]
+    json_include_raw: bool,
+
+    /// With "--format json", indent each object over several lines rather than printing one
+    /// object per line
+    #[clap(long)]
+    json_pretty: bool,
+
+    /// What separates the fields of "--format csv"
+    #[clap(long, value_enum, default_value_t = FieldSeparator::Comma)]
+    field_separator: FieldSeparator,
+
+    /// How to parse the input
+    #[clap(long, value_enum, default_value_t = InputFormat::Text)]
+    input_format: InputFormat,
+
+    /// Read the whole log and group messages by the given field
+    #[clap(long, value_enum, conflicts_with_all = ["follow", "follow_in_pager"])]
+    group_by: Option<GroupBy>,
+
+    /// Read the whole log and print a histogram of how many messages there are per severity,
+    /// logger or hour of the day, rather than the messages themselves
+    #[clap(
+        long,
+        value_enum,
+        conflicts_with_all = ["follow", "follow_in_pager", "group_by"]
+    )]
+    count_by: Option<CountBy>,
+
+    /// Print the number of lines that couldn't be parsed as (part of) a log message and exit, as
+    /// a quick check of how well the parser copes with a log
+    #[clap(
+        long,
+        conflicts_with_all = ["follow", "follow_in_pager", "all", "count_by", "group_by"]
+    )]
+    count_only_dangling: bool,
+
+    /// Follow the file like "-f", but print lines as they're read without parsing them, only
+    /// colored by their severity. Multi-line messages aren't grouped, so there's no waiting to
+    /// see if a message continues.
+    #[clap(
+        long,
+        conflicts_with_all = ["follow_in_pager", "all", "format", "count_by", "group_by"]
+    )]
+    raw_tail: bool,
+
+    /// Print lines as-is without parsing them, for reading logs that aren't from NSO
+    #[clap(long, conflicts_with = "input_format")]
+    passthrough: bool,
+
+    /// Parse log lines using a regex with the named groups "sev", "ts", "logger", "msg" and
+    /// optionally "thread", instead of the built-in NSO format
+    #[clap(long)]
+    parse_regex: Option<String>,
+
+    /// The strftime format of the "ts" group in "--parse-regex"
+    #[clap(
+        long,
+        requires = "parse_regex",
+        value_parser = valid_timefmt,
+        default_value = "%d-%b-%Y::%H:%M:%S%.3f"
+    )]
+    ts_format: String,
+
+    /// How to label the severity of each log message
+    #[clap(long, value_enum, default_value_t = LabelStyle::Short)]
+    labels: LabelStyle,
+
+    /// Show the severity of each log message as a colored glyph, same as "--labels icons"
+    #[clap(long, conflicts_with = "labels")]
+    icons: bool,
+
+    /// What decides the color of each log message. With "logger", every logger gets its own
+    /// color, which makes interleaved components easier to tell apart.
+    #[clap(long, value_enum, default_value_t = ColorBy::Severity)]
+    color_by: ColorBy,
+
+    /// Only use ASCII characters when drawing multi-line messages
+    #[clap(long)]
+    ascii: bool,
+
+    /// How many spaces to indent the box around multi-line messages with
+    #[clap(long, value_name = "N", default_value_t = DEFAULT_BOX_INDENT)]
+    indent_multiline: usize,
+
+    /// Only show log messages from this time onwards, e.g. "2024-01-02 15:04"
+    #[clap(long)]
+    since: Option<String>,
+
+    /// Only show log messages up until this time, e.g. "2024-01-02 15:04"
+    #[clap(long)]
+    until: Option<String>,
+
+    /// Only show log messages from the last DURATION, e.g. "15m", "1h" or "1h30m"
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "since")]
+    last: Option<TimeDelta>,
+
+    /// Only show log messages from today
+    #[clap(long, conflicts_with_all = ["since", "until", "last"])]
+    today: bool,
+
+    /// Only show log messages from yesterday
+    #[clap(long, conflicts_with_all = ["since", "until", "last", "today"])]
+    yesterday: bool,
+
+    /// Only show log messages within "--window" of this time, e.g. "2024-01-02 15:04:05"
+    #[clap(
+        long,
+        value_name = "TIMESTAMP",
+        conflicts_with_all = ["since", "until", "last", "today", "yesterday"]
+    )]
+    around: Option<String>,
+
+    /// How far before and after the "--around" time to show log messages, e.g. "30s" or "5m"
+    #[clap(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "5m",
+        requires = "around"
+    )]
+    window: TimeDelta,
+
+    /// Stop after showing N log messages, as a guard against dumping a huge file into the
+    /// terminal. 0 means no limit. Not applied while following.
+    #[clap(
+        long,
+        value_name = "N",
+        env = "NSO_LOG_READER_MAX_LINES",
+        default_value_t = 0
+    )]
+    max_lines: usize,
+
+    /// Wrap long messages at the terminal width rather than letting them overflow
+    #[clap(long)]
+    wrap: bool,
+
+    /// Wrap long messages at the given width (implies "--wrap")
+    #[clap(long, value_name = "N")]
+    clamp_width: Option<usize>,
+
+    /// Show every log message on a single line, with the line breaks of multi-line messages shown
+    /// as "⏎" (or "\n" with "--ascii")
+    #[clap(long, conflicts_with_all = ["wrap", "clamp_width", "pretty_width"])]
+    compact: bool,
+
+    /// Print only the message of each log line, without the severity, timestamp, logger and
+    /// thread, for piping the messages into other tools. Multi-line messages keep their line
+    /// breaks. Messages are colored by severity when writing to a terminal, unless NO_COLOR is
+    /// set.
+    #[clap(
+        short,
+        long,
+        conflicts_with_all = ["columns", "color_by", "format", "wrap", "clamp_width", "pretty_width"]
+    )]
+    message_only: bool,
+
+    /// What to show line breaks as with "--compact"
+    #[clap(long, value_name = "TEXT", requires = "compact")]
+    compact_glyph: Option<String>,
+
+    /// Wrap the lines of multi-line messages, such as tracebacks, to fit in N columns, or the
+    /// terminal width if N isn't given
+    #[clap(long, value_name = "N", num_args = 0..=1)]
+    pretty_width: Option<Option<usize>>,
+
+    /// Move a leading "[module:line]" marker out of each message and display it separately
+    #[clap(long)]
+    extract_source: bool,
+
+    /// Extract "key=value" style fields from messages, included as "fields" with "--format json"
+    #[clap(long)]
+    extract_fields: bool,
+
+    /// Remove ANSI escape sequences, such as colors, from messages before showing them
+    #[clap(long, visible_alias = "strip-ansi")]
+    strip_colors: bool,
+
+    /// Which columns to show and in which order, e.g. "time,severity,message" [default:
+    /// severity,time,logger,thread,message]
+    #[clap(long, value_enum, value_delimiter = ',')]
+    columns: Vec<Column>,
+
+    /// Show the time since the previous log message, after the time column. Same as adding
+    /// "elapsed" to "--columns".
+    #[clap(long)]
+    elapsed: bool,
+
+    /// With "--elapsed", highlight gaps between log messages longer than this many milliseconds
+    #[clap(long, value_name = "MS", default_value_t = 1000)]
+    elapsed_threshold: i64,
+
+    /// Show the thread of each log message (default)
+    #[clap(long, overrides_with = "no_thread")]
+    show_thread: bool,
+
+    /// Hide the thread of each log message
+    #[clap(long, overrides_with = "show_thread")]
+    no_thread: bool,
+
+    /// Hide lines that couldn't be parsed as (part of) a log message
+    #[clap(long)]
+    no_dangling: bool,
+
+    /// Mark dangling lines that look like the start of a log message but couldn't be parsed with
+    /// "unparsed:", to tell them apart from text that belongs to a cut-off log message
+    #[clap(long, visible_alias = "show-raw-on-parse-error")]
+    mark_dangling: bool,
+
+    /// Only show lines that couldn't be parsed, useful for finding gaps in the parser
+    #[clap(long, conflicts_with = "no_dangling")]
+    only_dangling: bool,
+
+    /// Only show log messages matching this regex
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new)]
+    grep: Option<Regex>,
+
+    /// With "--grep", only show log messages that don't match, like "grep -v". Useful for hiding
+    /// known noise.
+    #[clap(short = 'v', long, requires = "grep")]
+    invert_match: bool,
+
+    /// With "--grep", also show N log messages after each match. Dangling lines are shown along
+    /// with the log message before them, without counting towards N.
+    #[clap(short = 'A', long, value_name = "N", requires = "grep")]
+    after_context: Option<usize>,
+
+    /// With "--grep", also show N log messages (and their dangling lines) before each match
+    #[clap(short = 'B', long, value_name = "N", requires = "grep")]
+    before_context: Option<usize>,
+
+    /// With "--grep", also show N log messages before and after each match, like "-A N -B N"
+    #[clap(short = 'C', long, value_name = "N", requires = "grep")]
+    context: Option<usize>,
+
+    /// Only show log messages from the first one matching START to the next one matching END,
+    /// inclusive, like "sed '/START/,/END/p'"
+    #[clap(long, num_args = 2, value_names = ["START", "END"], value_parser = Regex::new)]
+    between: Option<Vec<Regex>>,
+
+    /// With "--between", show every slice from START to END, not just the first one
+    #[clap(long, requires = "between")]
+    between_all: bool,
+
+    /// Highlight matches of this regex in messages, without hiding anything. Can be given more
+    /// than once, each pattern gets its own color. Matches are only highlighted when writing to a
+    /// terminal and NO_COLOR isn't set.
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new)]
+    highlight: Vec<Regex>,
+
+    /// Show up to N raw lines of the input before and after each log message shown, exactly as
+    /// they were read. Useful for seeing how lines were grouped into messages. (Text input only)
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    context_lines_raw: usize,
+
+    /// Group consecutive unparseable lines, such as a cut-off message at the start of the input,
+    /// into a single entry rather than one per line
+    #[clap(long)]
+    merge_dangling: bool,
+
+    /// Only treat a line as the start of a new log message if it has a known severity, a line
+    /// like "<DATA> 02-Jan-2024::..." in embedded XML is kept as part of the message before it
+    #[clap(long)]
+    merge_continuations_aggressively: bool,
+
+    /// How closely a line has to look like the start of a log message to be one, rather than a
+    /// continuation of the message before it. "strict" helps with messages that contain
+    /// something that looks like a log line.
+    #[clap(long, value_enum, default_value_t = HeaderCheck::Lenient)]
+    header_check: HeaderCheck,
+
+    /// Skip lines at the start of the log until the first complete log message (implied when
+    /// using "-f", since tailing usually starts in the middle of a message)
+    #[clap(long)]
+    skip_partial: bool,
+
+    /// The glob used to find log files to match the patterns against
+    #[clap(
+        long = "glob",
+        value_name = "GLOB",
+        value_parser = valid_glob,
+        default_value = DEFAULT_GLOB
+    )]
+    file_glob: String,
+
+    /// Don't print warnings and notes to STDERR, only errors
+    #[clap(short, long)]
+    quiet: bool,
+
+    /// Print diagnostics to STDERR, such as why a log file was selected and how long it took to
+    /// read
+    #[clap(long)]
+    verbose: bool,
+
+    /// Ignore "-", "_" and whitespace when matching patterns against file names, so "l3 vpn"
+    /// matches "l3vpn"
+    #[clap(long)]
+    loose: bool,
+
+    /// Only look for the file with the shortest name among the matches, without sorting them
+    /// all. Faster with thousands of log files, but the other matches aren't considered.
+    #[clap(
+        long,
+        conflicts_with_all = ["prefer", "select", "sort_by", "print_matches", "all"]
+    )]
+    first_match_only: bool,
+
+    /// Which log file to open when several match
+    #[clap(long, value_enum, default_value_t = Prefer::Shortest)]
+    prefer: Prefer,
+
+    /// Print matches and exit, useful for troubleshooting. Prints a JSON array with
+    /// "--format json". Every match is listed in the "--sort-by" order regardless of "--prefer",
+    /// the one that would be opened is marked.
     #[clap(long)]
     print_matches: bool,
+
+    /// Print the full path of the log file that would be opened and exit, e.g. for
+    /// 'cat "$(nso-log-reader cfs --print-path)"'
+    #[clap(
+        long,
+        conflicts_with_all = ["print_matches", "edit", "follow", "follow_in_pager"]
+    )]
+    print_path: bool,
+
+    /// How to order the output of "--print-matches" (and the matches counted by "--select")
+    #[clap(long, value_enum, default_value_t = SortBy::Name)]
+    sort_by: SortBy,
+
+    /// Open the Nth match (starting at 1) in the order shown by "--print-matches", rather than
+    /// the one picked by "--prefer"
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    select: Option<u64>,
 }
 
 impl Args {
     fn custom_parse() -> Self {
         let mut args = Args::parse();
 
+        if args.raw_tail {
+            args.follow = true;
+        }
+
         if args.follow {
             args.time = true;
+            args.skip_partial = true;
+        }
+
+        // Checked here rather than with "requires", since "--raw-tail" implies "--follow"
+        if args.lines.is_some() && !args.follow {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "\"--lines\" only applies when following, with \"-f\" or \"--raw-tail\"",
+                )
+                .exit();
+        }
+
+        if !(0.0..=1.0).contains(&args.max_dangling_ratio) {
+            Args::command()
+                .error(
+                    clap::error::ErrorKind::ValueValidation,
+                    "\"--max-dangling-ratio\" must be between 0 and 1",
+                )
+                .exit();
         }
 
         args
@@ -68,37 +662,99 @@ impl Args {
 fn main() {
     let args = Args::custom_parse();
 
-    if let Err(error) = run_program(args) {
-        // Write the error to STDERR
-        eprintln!("{}", error);
-        exit(1);
+    match signals::install() {
+        Err(error) if !args.quiet => eprintln!("Warning: can't handle signals, {}", error),
+        _ => {}
+    }
+
+    match run_program(args) {
+        Ok(code) => exit(code),
+        Err(error) => {
+            // Write the error to STDERR
+            eprintln!("{}", error);
+            exit(EXIT_ERROR);
+        }
     }
 }
 
-fn run_program(args: Args) -> Result<(), String> {
+/// Returns the exit code
+fn run_program(args: Args) -> Result<i32, String> {
+    let started = Instant::now();
     let filename: String;
     let source: ParseSource;
+    // Where the source was opened from, if it's a file
+    let mut source_path: Option<PathBuf> = None;
+    // Any other files to follow, with --all
+    let mut more_sources: Vec<(String, ParseSource)> = Vec::new();
+    // Whether "--keep-going" skipped any files that couldn't be opened
+    let mut skipped_files = false;
     let mut target: Box<dyn std::io::Write>;
 
+    // Diagnostics for --verbose, --quiet wins if both are given
+    let verbose = args.verbose && !args.quiet;
+    macro_rules! diagnostic {
+        ($($arg:tt)*) => {
+            if verbose {
+                eprintln!($($arg)*);
+            }
+        };
+    }
+
+    if args.help_format {
+        print!("{}", format_help());
+        return Ok(EXIT_SUCCESS);
+    }
+
     //
     // Figure out the source
     //
 
-    if let Some(logfile) = args.logfile {
-        filename = Path::new(&logfile)
+    let explicit_stdin = args.logfile.as_deref() == Some("-");
+
+    if let Some(logfile) = args.logfile.as_ref().filter(|_| !explicit_stdin) {
+        filename = Path::new(logfile)
             .file_name()
             .unwrap()
             .to_str()
             .unwrap()
             .to_string();
+        diagnostic!("Reading {}, as given with -F", logfile);
+        source = open_logfile(Path::new(logfile), &args)?;
+        source_path = Some(PathBuf::from(logfile));
+    } else if !explicit_stdin && !args.patterns.is_empty() {
+        let logs_dir = logs_dir(args.log_dir.as_deref())?;
+        let matches = match_pattern(
+            &args.patterns,
+            &logs_dir,
+            &args.file_glob,
+            args.loose,
+            args.first_match_only,
+            args.quiet,
+        )?;
 
-        if args.follow {
-            source = tail(&logfile)?.into();
-        } else {
-            source = File::open(&logfile).map_err(|err| err.to_string())?.into();
+        // The preferred match is selected by default, regardless of the display order
+        let preferred = preferred_match(&matches, args.prefer)?;
+        let matches = sort_matches(matches, args.sort_by)?;
+
+        let best_match = match args.select {
+            None => preferred,
+            Some(n) => Some(matches.get(n as usize - 1).cloned().ok_or_else(|| {
+                format!("Can't select match {}, there are only {}", n, matches.len())
+            })?),
+        };
+
+        if args.print_matches && matches!(args.format, OutputFormat::Json) {
+            let reports = matches
+                .iter()
+                .map(|x| x.report(Some(x) == best_match.as_ref()))
+                .collect::<Vec<_>>();
+            println!(
+                "{}",
+                serde_json::to_string(&reports).map_err(|err| err.to_string())?
+            );
+
+            return Ok(EXIT_SUCCESS);
         }
-    } else if !args.patterns.is_empty() {
-        let matches = match_pattern(&args.patterns)?;
 
         if args.print_matches {
             match matches.len() {
@@ -107,12 +763,11 @@ fn run_program(args: Args) -> Result<(), String> {
                     "{}",
                     matches
                         .iter()
-                        .enumerate()
-                        .map(|(i, x)| {
-                            if i == 0 {
-                                "* ".to_string() + x
+                        .map(|x| {
+                            if Some(x) == best_match.as_ref() {
+                                "* ".to_string() + &x.filename
                             } else {
-                                "- ".to_string() + x
+                                "- ".to_string() + &x.filename
                             }
                         })
                         .collect::<Vec<_>>()
@@ -120,68 +775,736 @@ fn run_program(args: Args) -> Result<(), String> {
                 ),
             };
 
-            return Ok(());
+            return Ok(EXIT_SUCCESS);
         }
 
-        let best_match = matches.first().ok_or("No matches")?;
+        let best_match = best_match.ok_or("No matches")?;
 
-        let filepath = format!(
-            "{}/logs/{}",
-            std::env::var("NSO_RUN_DIR").unwrap(),
-            best_match,
+        diagnostic!(
+            "Selected {} out of {} files matching {:?}, {}",
+            best_match.path.display(),
+            matches.len(),
+            args.patterns,
+            match args.select {
+                None => match args.prefer {
+                    Prefer::Shortest => "since it has the shortest name".to_string(),
+                    Prefer::Longest => "since it has the longest name".to_string(),
+                    Prefer::Mtime => "since it was modified most recently".to_string(),
+                },
+                Some(n) => format!(
+                    "since it's match {} sorted by {}",
+                    n,
+                    format!("{:?}", args.sort_by).to_lowercase()
+                ),
+            }
         );
-        filename = Path::new(&filepath)
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_string();
 
-        if args.follow {
-            source = tail(&filepath)?.into();
-        } else {
-            source = File::open(&filepath).map_err(|err| err.to_string())?.into();
+        let others = matches.iter().filter(|x| args.all && **x != best_match);
+        for log_file in std::iter::once(&best_match).chain(others) {
+            match open_logfile(&log_file.path, &args) {
+                Ok(source) => {
+                    if more_sources.is_empty() {
+                        source_path = Some(log_file.path.clone());
+                    }
+                    more_sources.push((log_file.filename.clone(), source));
+                }
+                Err(err) if args.keep_going => {
+                    if !args.quiet {
+                        eprintln!("Warning: skipping {}, {}", log_file.filename, err);
+                    }
+                    skipped_files = true;
+                }
+                Err(err) => return Err(format!("{}: {}", log_file.filename, err)),
+            }
+        }
+
+        // The first file that could be opened is the main one, the rest are followed with it
+        if more_sources.is_empty() {
+            return Err("None of the matching log files could be opened".to_string());
         }
-    } else if atty::is(atty::Stream::Stdin) {
+        (filename, source) = more_sources.remove(0);
+        for (other, _) in &more_sources {
+            diagnostic!("Also following {}", other);
+        }
+    } else if let Some(default_log) = args
+        .default_log
+        .as_ref()
+        .filter(|_| !explicit_stdin && atty::is(atty::Stream::Stdin))
+    {
+        // No logfile arguments and STDIN is a TTY, but there's a default log to open instead
+        let path = match default_log.contains('/') {
+            true => PathBuf::from(default_log),
+            false => logs_dir(args.log_dir.as_deref())?.join(default_log),
+        };
+        filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| default_log.clone());
+        diagnostic!("Reading {}, the default log", path.display());
+        source = open_logfile(&path, &args)
+            .map_err(|err| format!("Can't open the default log {}: {}", path.display(), err))?;
+        source_path = Some(path);
+    } else if !explicit_stdin && atty::is(atty::Stream::Stdin) {
         // No logfile arguments and STDIN is a TTY, just print help msg and exit
-        return Args::command().print_help().map_err(|err| err.to_string());
+        return Args::command()
+            .print_help()
+            .map(|_| EXIT_SUCCESS)
+            .map_err(|err| err.to_string());
     } else {
         filename = "(STDIN)".into();
+        diagnostic!("Reading STDIN");
         source = stdin().into();
     }
 
+    if args.print_path {
+        let path = source_path.ok_or("Reading STDIN, there's no path to print")?;
+        let path = std::path::absolute(&path).map_err(|err| err.to_string())?;
+        println!("{}", path.display());
+
+        return Ok(EXIT_SUCCESS);
+    }
+
+    if args.check {
+        return check_log(source, &filename, &args);
+    }
+
+    if args.edit {
+        let path = source_path.ok_or("Can't edit STDIN, there's no file to open")?;
+        let editor = args
+            .editor
+            .clone()
+            .or_else(|| std::env::var("VISUAL").ok())
+            .or_else(|| std::env::var("EDITOR").ok())
+            .unwrap_or_else(|| "vi".to_string());
+        diagnostic!("Opening {} in {}", path.display(), editor);
+
+        return edit(&path, &editor);
+    }
+
+    diagnostic!(
+        "Waiting {}ms for more lines before considering a log message complete",
+        READ_TIMEOUT.as_millis()
+    );
+
+    // Output is flushed after every log message when reading a stream such as a pipe or "tail",
+    // where messages trickle in and should be seen as soon as they're complete. Flushing costs a
+    // write per message, which adds up when reading a whole file, so regular files are buffered.
+    let streaming = !source.is_regular_file() || !more_sources.is_empty();
+    let unbuffered = args.unbuffered || streaming;
+
+    // Progress can only be known for regular files, not for streams
+    let mut progress = match (&source, args.progress) {
+        (ParseSource::File(file), true) => Some(Progress::new(file)?),
+        _ => None,
+    };
+
     //
     // Figure out the target
     //
-    // (--follow implies --cat)
+    // (--follow and --count-only-dangling imply --cat)
     //
-    if args.cat || args.follow {
-        target = Box::new(std::io::stdout());
+    // Opened first, so we fail before the pager is started
+    let tee_file = args.tee.as_deref().map(open_tee_file).transpose()?;
+
+    if args.cat || args.follow || args.count_only_dangling {
+        target = Box::new(BufWriter::new(std::io::stdout()));
     } else {
-        target = Box::new(pager(&filename)?);
+        target = pager(&filename, &args)?;
+    }
+
+    if let Some(tee_file) = tee_file {
+        target = Box::new(Tee::new(target, tee_file).keep_colors(args.tee_colors));
+    }
+
+    if args.raw_tail {
+        for logline in parse_raw(source).take_while(|_| !signals::interrupted()) {
+            if let LogLine::Dangling(line) = logline {
+                print_raw_tail_line(&line.text, &mut target)
+                    .and_then(|_| target.flush())
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        target.flush().map_err(|err| err.to_string())?;
+
+        return Ok(signals::exit_code().unwrap_or(EXIT_SUCCESS));
     }
 
     //
     // Parse away!
     //
 
-    for logline in parse_log(source) {
-        print_logline(
-            &logline,
-            &mut target,
-            match args.time {
-                true => &DateFormat::TimeOnly,
-                false => &DateFormat::Full,
+    let tz_from_filename = match args.tz_from_filename {
+        true => timezone_from_filename(&filename),
+        false => None,
+    };
+    if let Some(timezone) = &tz_from_filename {
+        diagnostic!("Showing times in {:?}, going by the file name", timezone);
+    }
+
+    let format_options = FormatOptions {
+        dateformat: match (args.timestamps, args.timefmt.clone(), args.time) {
+            (TimestampMode::Relative, _, _) => DateFormat::Relative(Cell::new(None)),
+            (_, Some(timefmt), _) => DateFormat::Custom(timefmt),
+            (_, None, true) => DateFormat::TimeOnly,
+            (_, None, false) => DateFormat::Full,
+        },
+        // --local is the default, so only --utc needs checking
+        timezone: match (tz_from_filename, args.utc) {
+            (Some(timezone), _) => timezone,
+            (None, true) => Timezone::Utc,
+            (None, false) => Timezone::Local,
+        },
+        labels: match args.icons {
+            true => LabelStyle::Icons,
+            false => args.labels,
+        },
+        ascii: args.ascii,
+        box_indent: args.indent_multiline,
+        highlights: args.highlight.clone(),
+        json_pretty: args.json_pretty,
+        prefix_file: args.prefix_source || (!more_sources.is_empty() && !args.no_prefix_source),
+        field_separator: args.field_separator,
+        color_by: args.color_by,
+        mark_unparsed: args.mark_dangling,
+        columns: match args.columns.is_empty() {
+            true => DEFAULT_COLUMNS.to_vec(),
+            false => args.columns.clone(),
+        }
+        .into_iter()
+        .filter(|column| !(args.no_thread && *column == Column::Thread))
+        .flat_map(|column| match (column, args.elapsed) {
+            (Column::Time, true) => vec![Column::Time, Column::Elapsed],
+            _ => vec![column],
+        })
+        .collect(),
+        elapsed_threshold: TimeDelta::milliseconds(args.elapsed_threshold),
+        wrap_width: match (args.clamp_width, args.wrap) {
+            (Some(width), _) => Some(width),
+            (None, true) => Some(terminal_width()),
+            (None, false) => None,
+        },
+        box_wrap_width: args
+            .pretty_width
+            .map(|width| width.unwrap_or_else(terminal_width)),
+        message_only: args.message_only,
+        use_color: atty::is(atty::Stream::Stdout)
+            && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()),
+        compact: match (args.compact, &args.compact_glyph, args.ascii) {
+            (false, _, _) => None,
+            (true, Some(glyph), _) => Some(glyph.clone()),
+            (true, None, true) => Some("\\n".to_string()),
+            (true, None, false) => Some("⏎".to_string()),
+        },
+    };
+
+    let loglines: Box<dyn Iterator<Item = LogLine>> = match more_sources.is_empty() {
+        true if args.prefix_source => {
+            let filename = filename.clone();
+            Box::new(
+                parse_source(source, &filename, &args)?.map(move |mut logline| {
+                    logline.set_file(filename.clone());
+                    logline
+                }),
+            )
+        }
+        true => parse_source(source, &filename, &args)?,
+        false => {
+            let mut sources = vec![(filename.clone(), parse_source(source, &filename, &args)?)];
+            for (filename, source) in more_sources {
+                let loglines = parse_source(source, &filename, &args)?;
+                sources.push((filename, loglines));
+            }
+
+            Box::new(follow_many(sources))
+        }
+    };
+
+    if args.count_only_dangling {
+        // Merged dangling lines still count as one per line
+        let count: usize = loglines
+            .map(|logline| match logline {
+                LogLine::Normal(_) => 0,
+                LogLine::Dangling(dangling) => dangling.text.split('\n').count(),
+            })
+            .sum();
+        writeln!(target, "{}", count)
+            .and_then(|_| target.flush())
+            .map_err(|err| err.to_string())?;
+
+        return Ok(EXIT_SUCCESS);
+    }
+
+    let timezone = &format_options.timezone;
+    let time_range = match () {
+        _ if args.today => TimeRange {
+            since: Some(start_of_day(0, timezone)?),
+            until: None,
+        },
+        _ if args.yesterday => TimeRange {
+            since: Some(start_of_day(1, timezone)?),
+            until: Some(start_of_day(0, timezone)? - TimeDelta::nanoseconds(1)),
+        },
+        _ if args.around.is_some() => {
+            let around = parse_timestamp(args.around.as_deref().unwrap(), timezone)?;
+            TimeRange {
+                since: Some(around - args.window),
+                until: Some(around + args.window),
+            }
+        }
+        _ => TimeRange {
+            since: match args.last {
+                Some(last) => Some(Utc::now() - last),
+                None => args
+                    .since
+                    .map(|since| parse_timestamp(&since, timezone))
+                    .transpose()?,
             },
-        )
-        .map_err(|err| err.to_string())?;
+            until: args
+                .until
+                .map(|until| parse_timestamp(&until, timezone))
+                .transpose()?,
+        },
+    };
+
+    let filtering = time_range.is_active() || args.grep.is_some() || args.between.is_some();
+    let mut matched = false;
+
+    let following = args.follow || args.follow_in_pager;
+    let max_lines = Some(args.max_lines).filter(|&max| max > 0 && !following);
+    let mut capped = false;
+
+    // Whether we're inside a "--between" slice, and whether a slice has ended
+    let mut in_slice = false;
+    let mut slice_ended = false;
+
+    let show_elapsed = format_options.columns.contains(&Column::Elapsed);
+    let mut previous = None;
+
+    let (mut normal_count, mut dangling_count) = (0, 0);
+
+    let loglines = loglines.inspect(|logline| {
+        if let Some(progress) = &mut progress {
+            progress.update();
+        }
+
+        match logline {
+            LogLine::Normal(_) => normal_count += 1,
+            LogLine::Dangling(_) => dangling_count += 1,
+        }
+    });
+
+    // Warn if the input doesn't look like an NSO log at all, but not when we're asked to produce
+    // structured output or not to parse anything
+    let detect_not_nso = !args.quiet
+        && matches!(args.format, OutputFormat::Pretty)
+        && matches!(args.input_format, InputFormat::Text)
+        && !args.passthrough;
+    // How many lines have been sampled, and how many of them were dangling. Lines are counted
+    // rather than log lines, since "--merge-dangling" merges any number of lines into one.
+    let sample = Cell::new((0, 0));
+    let warn_not_nso = |(sampled, dangling): (usize, usize)| {
+        if sampled > 0 && dangling as f64 / sampled as f64 > NOT_NSO_DANGLING_RATIO {
+            eprintln!("This doesn't look like an NSO log; showing raw lines.");
+        }
+    };
+
+    let loglines = loglines
+        .inspect(|logline| {
+            let (sampled, dangling) = sample.get();
+            if !detect_not_nso || sampled >= NOT_NSO_SAMPLE_SIZE {
+                return;
+            }
+
+            let (lines, is_dangling) = match logline {
+                LogLine::Normal(logline) => (logline.message.lines().count(), false),
+                LogLine::Dangling(logline) => (logline.text.lines().count(), true),
+            };
+            let lines = lines.clamp(1, NOT_NSO_SAMPLE_SIZE - sampled);
+            let sample_now = match is_dangling {
+                true => (sampled + lines, dangling + lines),
+                false => (sampled + lines, dangling),
+            };
+            sample.set(sample_now);
+
+            if sample_now.0 == NOT_NSO_SAMPLE_SIZE {
+                warn_not_nso(sample_now);
+            }
+        })
+        // Input shorter than the sample is judged once it ends
+        .chain(std::iter::from_fn(|| {
+            if detect_not_nso && sample.get().0 < NOT_NSO_SAMPLE_SIZE {
+                warn_not_nso(sample.get());
+                sample.set((NOT_NSO_SAMPLE_SIZE, 0));
+            }
+            None
+        }));
+
+    // Only leading dangling lines are skipped, later ones are let through, as are the leading
+    // ones if there are too many for an NSO log. Everything is dangling with "--passthrough", so
+    // there's no partial log message to skip.
+    let loglines: Box<dyn Iterator<Item = LogLine>> = match args.skip_partial && !args.passthrough {
+        true => Box::new(SkipPartial::new(loglines, NOT_NSO_SAMPLE_SIZE)),
+        false => Box::new(loglines),
+    };
+
+    let loglines = loglines.map(|mut logline| {
+        if args.strip_colors {
+            let text = match &mut logline {
+                LogLine::Normal(logline) => &mut logline.message,
+                LogLine::Dangling(dangling) => &mut dangling.text,
+            };
+            *text = strip_ansi(text);
+        }
+        if let LogLine::Normal(logline) = &mut logline {
+            if args.extract_source {
+                logline.extract_source();
+            }
+            if args.extract_fields {
+                logline.fields = extract_fields(&logline.message);
+            }
+        }
+        logline
+    });
+
+    let loglines = TimeFilter::new(loglines, time_range, !following)
+        .filter(|logline| match logline {
+            LogLine::Normal(_) => !args.only_dangling,
+            LogLine::Dangling(_) => !args.no_dangling,
+        })
+        // Only let through the "--between" slices
+        .map_while(|logline| {
+            let Some([start, end]) = args.between.as_deref() else {
+                return Some(Some(logline));
+            };
+            // Without "--between-all" there's nothing more to show once the first slice has ended
+            if slice_ended && !args.between_all {
+                return None;
+            }
+
+            let text = match &logline {
+                LogLine::Normal(logline) => &logline.message,
+                LogLine::Dangling(dangling) => &dangling.text,
+            };
+
+            // Like sed, the end pattern is only checked from the message after the start
+            if !in_slice {
+                in_slice = start.is_match(text);
+                return Some(in_slice.then_some(logline));
+            }
+            if end.is_match(text) {
+                in_slice = false;
+                slice_ended = true;
+            }
+
+            Some(Some(logline))
+        })
+        .flatten();
+
+    let loglines = GrepFilter::new(
+        loglines,
+        args.grep.clone(),
+        args.before_context.or(args.context).unwrap_or(0),
+        args.after_context.or(args.context).unwrap_or(0),
+    )
+    .invert(args.invert_match)
+    // Anything that gets through counts, including dangling lines matched by --grep
+    .inspect(|_| matched = true)
+    // The time since the previous log message, dangling lines have no time so they're skipped
+    .map(|mut logline| {
+        if let (LogLine::Normal(logline), true) = (&mut logline, show_elapsed) {
+            logline.elapsed = previous.map(|previous| logline.datetime - previous);
+            previous = Some(logline.datetime);
+        }
+        logline
+    })
+    // Stop early if we get SIGINT or SIGTERM
+    .take_while(|_| !signals::interrupted());
+
+    // Only log messages count towards --max-lines, and nothing more is read once it's reached
+    let mut loglines = loglines;
+    let mut shown = 0;
+    let reached_max = &mut capped;
+    let loglines = std::iter::from_fn(move || {
+        if max_lines.is_some_and(|max_lines| shown >= max_lines) {
+            *reached_max = true;
+            return None;
+        }
+
+        let logline = loglines.next()?;
+        if let LogLine::Normal(_) = logline {
+            shown += 1;
+        }
+        Some(logline)
+    });
+
+    if matches!(args.format, OutputFormat::Csv) {
+        print_csv_header(&mut target, &format_options).map_err(|err| err.to_string())?;
+    }
+
+    match (args.count_by, args.group_by) {
+        (Some(count_by), _) => {
+            let mut histogram = Histogram::new(count_by, format_options.timezone);
+
+            for logline in loglines {
+                histogram.add(&logline);
+            }
+
+            histogram
+                .print(&mut target, args.ascii)
+                .map_err(|err| err.to_string())?;
+        }
+        (None, None) => {
+            for logline in loglines {
+                print(&logline, &mut target, args.format, &format_options)?;
+                if unbuffered {
+                    target.flush().map_err(|err| err.to_string())?;
+                }
+            }
+        }
+        (None, Some(GroupBy::Logger)) => {
+            // Dangling lines have no logger, they end up in the "" group, which sorts first
+            let mut groups: BTreeMap<String, Vec<LogLine>> = BTreeMap::new();
+
+            for logline in loglines {
+                let logger_name = match &logline {
+                    LogLine::Normal(logline) => logline.logger_name.clone(),
+                    LogLine::Dangling(_) => String::new(),
+                };
+
+                groups.entry(logger_name).or_default().push(logline);
+            }
+
+            for (i, (logger_name, mut group)) in groups.into_iter().enumerate() {
+                // Headers would break structured output, there the grouping is just an ordering
+                if matches!(args.format, OutputFormat::Pretty) {
+                    if i > 0 {
+                        writeln!(target).map_err(|err| err.to_string())?;
+                    }
+
+                    print_group_header(
+                        match logger_name.as_str() {
+                            "" => "(unparsed lines)",
+                            name => name,
+                        },
+                        &mut target,
+                        &format_options,
+                    )
+                    .map_err(|err| err.to_string())?;
+                }
+
+                group.sort_by_key(|logline| match logline {
+                    LogLine::Normal(logline) => Some(logline.datetime),
+                    LogLine::Dangling(_) => None,
+                });
+
+                for logline in &group {
+                    print(logline, &mut target, args.format, &format_options)?;
+                }
+            }
+        }
+    }
+
+    target.flush().map_err(|err| err.to_string())?;
+
+    if capped && !args.quiet {
+        eprintln!(
+            "(stopped at {} log messages; use --max-lines 0 for unlimited)",
+            args.max_lines
+        );
+    }
+
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+
+    diagnostic!(
+        "Read {} log messages and {} dangling lines in {:.2?}",
+        normal_count,
+        dangling_count,
+        started.elapsed()
+    );
+
+    if let Some(code) = signals::exit_code() {
+        return Ok(code);
+    }
+
+    if skipped_files {
+        return Ok(EXIT_ERROR);
+    }
+
+    match filtering && !matched {
+        true => Ok(EXIT_NO_MATCHES),
+        false => Ok(EXIT_SUCCESS),
+    }
+}
+
+fn print(
+    logline: &LogLine,
+    target: &mut impl Write,
+    format: OutputFormat,
+    format_options: &FormatOptions,
+) -> Result<(), String> {
+    // Raw context would break structured output
+    let raw_context = match format {
+        OutputFormat::Pretty => logline.raw_context(),
+        _ => None,
+    };
+
+    if let Some(raw_context) = raw_context {
+        print_raw_lines(&raw_context.before, target, format_options)
+            .map_err(|err| err.to_string())?;
+    }
+
+    match format {
+        OutputFormat::Pretty => print_logline(logline, target, format_options),
+        OutputFormat::Json => print_logline_json(logline, target, format_options),
+        OutputFormat::Csv => print_logline_csv(logline, target, format_options),
+    }
+    .map_err(|err| err.to_string())?;
+
+    if let Some(raw_context) = raw_context {
+        print_raw_lines(&raw_context.after, target, format_options)
+            .and_then(|_| print_raw_separator(target))
+            .map_err(|err| err.to_string())?;
     }
 
     Ok(())
 }
 
+/// Parses the last "--check-lines" lines of a log and prints how many couldn't be parsed
+///
+/// Returns `EXIT_CHECK_FAILED` if the share of dangling lines is above "--max-dangling-ratio". The
+/// window may start in the middle of a log message, whose lines then count as dangling, so the
+/// ratio shouldn't be set too close to 0.
+///
+fn check_log(source: ParseSource, filename: &str, args: &Args) -> Result<i32, String> {
+    let mut lines: VecDeque<String> = VecDeque::with_capacity(args.check_lines.get());
+    let mut reader = BufReader::new(source.into_reader());
+    let mut line = Vec::new();
+    loop {
+        let end = match reader.read_until(b'\n', &mut line) {
+            Ok(0) => true,
+            Ok(_) if !line.ends_with(b"\n") => continue,
+            Ok(_) => false,
+            // Streams time out while waiting for more input, which is no reason to give up here
+            Err(err) if matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {
+                continue
+            }
+            Err(err) => return Err(format!("{}: {}", filename, err)),
+        };
+        if end && line.is_empty() {
+            break;
+        }
+
+        // Not UTF-8, so it can't be parsed
+        let text = String::from_utf8(std::mem::take(&mut line))
+            .map_err(|_| format!("{}: stream did not contain valid UTF-8", filename))?;
+        if lines.len() == args.check_lines.get() {
+            lines.pop_front();
+        }
+        lines.push_back(text.trim_end_matches(['\n', '\r']).to_string());
+
+        if end {
+            break;
+        }
+    }
+
+    let text = Vec::from(lines).join("\n");
+    let (mut messages, mut dangling) = (0, 0);
+    let loglines = parse_str(&text, line_format(filename, args)?)
+        .strict(args.merge_continuations_aggressively)
+        .header_check(args.header_check);
+    for logline in loglines {
+        match logline {
+            LogLine::Normal(_) => messages += 1,
+            // Dangling lines in a row may have been merged into one
+            LogLine::Dangling(logline) => dangling += logline.text.lines().count().max(1),
+        }
+    }
+
+    let line_count = text.lines().count();
+    let ratio = match line_count {
+        0 => 0.0,
+        _ => dangling as f64 / line_count as f64,
+    };
+    let passed = ratio <= args.max_dangling_ratio;
+
+    println!(
+        "{}: {}, {} lines, {} log messages, {} dangling lines ({:.1}%, at most {:.1}% allowed)",
+        match passed {
+            true => "OK",
+            false => "FAIL",
+        },
+        filename,
+        line_count,
+        messages,
+        dangling,
+        ratio * 100.0,
+        args.max_dangling_ratio * 100.0
+    );
+
+    match passed {
+        true => Ok(EXIT_SUCCESS),
+        false => Ok(EXIT_CHECK_FAILED),
+    }
+}
+
+/// How to recognize log messages in a source according to the input options
+fn line_format(filename: &str, args: &Args) -> Result<LineFormat, String> {
+    match &args.parse_regex {
+        Some(parse_regex) => LineFormat::regex(parse_regex, args.ts_format.clone()),
+        None => Ok(LineFormat::Nso(MessageDelimiter::for_filename(filename))),
+    }
+}
+
+/// Picks the parser for a source according to the input options
+fn parse_source(
+    source: ParseSource,
+    filename: &str,
+    args: &Args,
+) -> Result<Box<dyn Iterator<Item = LogLine> + Send>, String> {
+    let line_format = line_format(filename, args)?;
+
+    Ok(match args.input_format {
+        _ if args.passthrough => Box::new(parse_raw(source)),
+        InputFormat::Text => Box::new(
+            parse_log(source, line_format)
+                .merge_dangling(args.merge_dangling)
+                .keep_raw(args.json_include_raw)
+                .strict(args.merge_continuations_aggressively)
+                .header_check(args.header_check)
+                .no_lookahead(args.no_lookahead)
+                .raw_context(args.context_lines_raw),
+        ),
+        InputFormat::Jsonl => Box::new(parse_jsonl(source)),
+    })
+}
+
+fn open_logfile(filepath: &Path, args: &Args) -> Result<ParseSource, String> {
+    let poll_interval = Duration::from_millis(args.poll_interval);
+
+    // Tail only complains on its STDERR, so make sure the file can be read first
+    if args.follow || args.follow_in_pager {
+        File::open(filepath).map_err(|err| err.to_string())?;
+    }
+
+    if args.follow {
+        let lines = args.lines.unwrap_or(DEFAULT_FOLLOW_LINES).to_string();
+        Ok(tail(filepath, &lines, poll_interval)?.into())
+    } else if args.follow_in_pager {
+        // Start from the first line, the pager shows the history
+        Ok(tail(filepath, "+1", poll_interval)?.into())
+    } else {
+        Ok(File::open(filepath).map_err(|err| err.to_string())?.into())
+    }
+}
+
 /// Parses a log file from the logfile command line option
-fn pager(filename: &str) -> Result<impl Write, String> {
+///
+/// Falls back to the built-in pager if "less" can't be started, unless following, which the
+/// built-in pager can't do.
+///
+fn pager(filename: &str, args: &Args) -> Result<Box<dyn Write>, String> {
     let mut prompt = format!("Reading log: {}", filename);
     prompt = prompt.replace(':', "\\:");
     prompt = prompt.replace('.', "\\.");
@@ -189,18 +1512,189 @@ fn pager(filename: &str) -> Result<impl Write, String> {
 
     prompt = format!("{} ?e(END):[page %dm/%D] [%Pt\\%].", prompt);
 
-    let pager_cmd = Exec::cmd("less")
-        .arg("-SR")
-        .arg("+G")
-        .arg(format!("--prompt={}", prompt));
+    let mut pager_cmd = Exec::cmd("less").arg("-SR");
+    if args.follow_in_pager {
+        pager_cmd = pager_cmd.arg("+F");
+    } else if let Some(pattern) = &args.jump_to {
+        pager_cmd = pager_cmd.arg(format!("+/{}", less_pattern(pattern.as_str())));
+    } else if !args.top {
+        pager_cmd = pager_cmd.arg("+G");
+    }
+    let pager_cmd = pager_cmd.arg(format!("--prompt={}", prompt));
+
+    match pager_cmd.stream_stdin() {
+        // Like STDOUT, written to in small pieces, the built-in pager buffers everything anyway
+        Ok(pager) => Ok(Box::new(BufWriter::new(pager))),
+        #[cfg(feature = "builtin-pager")]
+        Err(_) if !args.follow_in_pager => Ok(Box::new(
+            builtin_pager::BuiltinPager::new(filename)
+                .start_at_top(args.top)
+                .jump_to(args.jump_to.clone()),
+        )),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Escapes a search pattern for a "less" command line option such as "+/PATTERN"
+///
+/// The pattern is passed straight to "less" without a shell, but "less" reads some characters at
+/// the start of a search as modifiers rather than as part of the pattern, e.g. "!" to search for
+/// lines that don't match.
+///
+fn less_pattern(pattern: &str) -> String {
+    match pattern.starts_with(['!', '*', '@']) {
+        true => format!("\\{}", pattern),
+        false => pattern.to_string(),
+    }
+}
 
-    pager_cmd.stream_stdin().map_err(|err| err.to_string())
+/// The width of the terminal, or 80 if it can't be found out
+fn terminal_width() -> usize {
+    terminal_size::terminal_size()
+        .map(|(width, _)| width.0 as usize)
+        .unwrap_or(80)
 }
 
 fn file_exists(filepath: &str) -> Result<String, String> {
-    if Path::new(filepath).exists() {
-        Ok(String::from(filepath))
+    let filepath = expand_path(filepath)?;
+
+    // "-" means STDIN
+    if filepath == "-" {
+        Ok(filepath)
+    } else if Path::new(&filepath).is_dir() {
+        Err("Is a directory".to_string())
+    } else if Path::new(&filepath).exists() {
+        Ok(filepath)
     } else {
         Err("File does not exist".to_string())
     }
 }
+
+/// Expands a leading "~" and any "$VAR" or "${VAR}" in a path, like a shell would
+///
+/// Paths are usually expanded by the shell already, this is for when they aren't, e.g. when
+/// quoted or given in a config. A "$" that isn't followed by a variable name is left alone.
+///
+fn expand_path(path: &str) -> Result<String, String> {
+    let var = |name: &str| {
+        std::env::var(name).map_err(|_| format!("Environment variable not set: {}", name))
+    };
+
+    let mut expanded = String::new();
+    let mut rest = path;
+
+    if rest == "~" || rest.starts_with("~/") {
+        expanded.push_str(&var("HOME")?);
+        rest = &rest[1..];
+    }
+
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| format!("Unclosed \"${{\" in path: {}", path))?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            // Like in a shell, names can't start with a digit
+            None if rest.starts_with(|c: char| c.is_ascii_digit()) => ("", rest),
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+
+        expanded.push_str(&var(name)?);
+        rest = after;
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+fn valid_timefmt(timefmt: &str) -> Result<String, String> {
+    validate_strftime(timefmt)?;
+
+    Ok(String::from(timefmt))
+}
+
+fn valid_glob(pattern: &str) -> Result<String, String> {
+    glob::Pattern::new(pattern).map_err(|err| format!("Invalid glob pattern: {}", err))?;
+
+    Ok(String::from(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_path_expands_tilde_and_variables() {
+        let home = std::env::var("HOME").unwrap();
+        std::env::set_var("NSO_LOG_READER_TEST_DIR", "/opt/nso");
+
+        assert_eq!(expand_path("~").unwrap(), home);
+        assert_eq!(expand_path("~/logs").unwrap(), format!("{}/logs", home));
+        assert_eq!(
+            expand_path("$NSO_LOG_READER_TEST_DIR/logs").unwrap(),
+            "/opt/nso/logs"
+        );
+        assert_eq!(
+            expand_path("${NSO_LOG_READER_TEST_DIR}-old/logs").unwrap(),
+            "/opt/nso-old/logs"
+        );
+    }
+
+    #[test]
+    fn expand_path_leaves_other_text_alone() {
+        for path in ["logs/~user", "~user/logs", "cost$", "$1.log", "a$-b", "-"] {
+            assert_eq!(expand_path(path).unwrap(), path);
+        }
+    }
+
+    #[test]
+    fn expand_path_rejects_unset_variables() {
+        std::env::remove_var("NSO_LOG_READER_TEST_UNSET");
+
+        assert_eq!(
+            expand_path("$NSO_LOG_READER_TEST_UNSET/logs"),
+            Err("Environment variable not set: NSO_LOG_READER_TEST_UNSET".to_string())
+        );
+        assert_eq!(
+            expand_path("${NSO_LOG_READER_TEST_UNSET}"),
+            Err("Environment variable not set: NSO_LOG_READER_TEST_UNSET".to_string())
+        );
+        assert!(expand_path("${HOME/logs").unwrap_err().contains("Unclosed"));
+    }
+
+    #[test]
+    fn file_exists_expands_the_path() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ncs.log"), "").unwrap();
+        std::env::set_var("NSO_LOG_READER_TEST_LOGS", dir.path());
+
+        assert_eq!(
+            file_exists("${NSO_LOG_READER_TEST_LOGS}/ncs.log").unwrap(),
+            dir.path().join("ncs.log").to_str().unwrap()
+        );
+        assert_eq!(
+            file_exists("$NSO_LOG_READER_TEST_LOGS"),
+            Err("Is a directory".to_string())
+        );
+        assert_eq!(
+            file_exists("$NSO_LOG_READER_TEST_LOGS/missing.log"),
+            Err("File does not exist".to_string())
+        );
+        assert_eq!(file_exists("-").unwrap(), "-");
+    }
+}
@@ -1,38 +1,179 @@
 use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
     fs::File,
-    io::{BufRead, BufReader, Lines, Read, Stdin},
+    io::{BufRead, BufReader, Cursor, Lines, Read, Stdin},
+    ops::Range,
     os::fd::AsRawFd,
-    process::ChildStdout,
     str::FromStr,
     time::Duration,
 };
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
+use flate2::read::MultiGzDecoder;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use timeout_readwrite::TimeoutReadExt;
 
-#[derive(Clone, Copy, Debug)]
+use crate::signals;
+use crate::tail::Tail;
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum Severity {
+    Trace,
     Debug,
     Info,
     Warning,
     Error,
     Critical,
+    /// A severity we don't know about (yet), with the token as written in the log
+    #[serde(untagged)]
+    Unknown(String),
 }
 
-#[derive(Debug)]
+impl Severity {
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, Severity::Unknown(_))
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Trace => "TRACE",
+            Severity::Debug => "DEBUG",
+            Severity::Info => "INFO",
+            Severity::Warning => "WARNING",
+            Severity::Error => "ERROR",
+            Severity::Critical => "CRITICAL",
+            Severity::Unknown(token) => token,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NormalLogLine {
     pub severity: Severity,
     pub datetime: chrono::DateTime<chrono::Utc>,
     pub logger_name: String,
     pub thread: String,
     pub message: String,
+    /// The originating module and line number, e.g. "my_service.main:42", only extracted from
+    /// the message on request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// "key=value" style fields found in the message, only extracted on request
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// The name of the log file this came from, only set when following several files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// The raw lines around this message, only kept on request
+    #[serde(skip)]
+    pub raw_context: Option<RawContext>,
+    /// Time since the previous log message shown, only calculated for the "elapsed" column
+    #[serde(skip)]
+    pub elapsed: Option<chrono::TimeDelta>,
+    /// The lines this message was parsed from, exactly as they were read, only kept on request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+impl NormalLogLine {
+    /// Moves a leading "[module:line] " marker out of the message and into `source`
+    ///
+    /// This is deliberately strict, the message is left alone unless the marker is clearly
+    /// delimited and contains only a dotted module path, a colon and a line number.
+    ///
+    pub fn extract_source(&mut self) {
+        let Some(end) = self.message.find("] ") else {
+            return;
+        };
+        let Some(marker) = self.message[..end].strip_prefix('[') else {
+            return;
+        };
+        let Some((module, line)) = marker.split_once(':') else {
+            return;
+        };
+
+        let valid_module = !module.is_empty()
+            && module
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+        let valid_line = !line.is_empty() && line.chars().all(|c| c.is_ascii_digit());
+
+        if valid_module && valid_line {
+            self.source = Some(marker.to_string());
+            self.message = self.message[end + 2..].to_string();
+        }
+    }
 }
 
 impl FromStr for NormalLogLine {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        parse_line(s).ok_or(())
+        NormalLogLineRef::parse(s, MessageDelimiter::Either)
+            .map(NormalLogLineRef::into_owned)
+            .ok_or(())
+    }
+}
+
+/// Renders the log line the way NSO writes it, without colors
+impl Display for NormalLogLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "<{}> {} {} {}: ",
+            self.severity,
+            self.datetime.format("%d-%b-%Y::%H:%M:%S%.3f"),
+            self.logger_name,
+            self.thread,
+        )?;
+
+        if let Some(source) = &self.source {
+            write!(f, "[{}] ", source)?;
+        }
+
+        f.write_str(&self.message)
+    }
+}
+
+/// A log message borrowing its text from the line it was parsed from, for parsing without
+/// allocating
+///
+/// Only single lines are parsed like this, so this never has more than the first line of a
+/// multi-line message.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalLogLineRef<'a> {
+    pub severity: Severity,
+    pub datetime: chrono::DateTime<chrono::Utc>,
+    pub logger_name: &'a str,
+    pub thread: &'a str,
+    pub message: &'a str,
+}
+
+impl<'a> NormalLogLineRef<'a> {
+    pub fn parse(line: &'a str, delimiter: MessageDelimiter) -> Option<Self> {
+        parse_line(line, delimiter)
+    }
+
+    pub fn into_owned(self) -> NormalLogLine {
+        NormalLogLine {
+            severity: self.severity,
+            datetime: self.datetime,
+            logger_name: self.logger_name.to_string(),
+            thread: self.thread.to_string(),
+            message: self.message.to_string(),
+            source: None,
+            fields: BTreeMap::new(),
+            file: None,
+            raw_context: None,
+            elapsed: None,
+            raw: None,
+        }
     }
 }
 
@@ -41,23 +182,115 @@ impl FromStr for NormalLogLine {
 /// This happens when the log starts with a cut-off multi-line log message, common when parsing
 /// from "tail".
 ///
-#[derive(Debug)]
+/// By default each such line becomes its own dangling line. With `LogParser::merge_dangling`,
+/// consecutive lines are grouped into one, with the lines separated by newlines.
+///
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DanglingLogLine {
     pub text: String,
+    /// See `NormalLogLine::file`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// See `NormalLogLine::raw_context`
+    #[serde(skip)]
+    pub raw_context: Option<RawContext>,
+    /// Why the line couldn't be parsed, if it was parsed at all. When lines are merged, any
+    /// malformed line makes them all count as malformed.
+    #[serde(skip)]
+    pub parse_error: Option<ParseLineError>,
+}
+
+impl DanglingLogLine {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            file: None,
+            raw_context: None,
+            parse_error: None,
+        }
+    }
 }
 
+/// Why a line couldn't be parsed as the start of a log message
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseLineError {
+    /// The line doesn't look like the start of a log message, so it's text belonging to one, e.g.
+    /// a line of a traceback
+    Continuation,
+    /// The line starts like a log message, but the rest of it couldn't be parsed
+    Malformed,
+}
+
+/// Lines of the input around a log message, exactly as they were read
 #[derive(Debug)]
+pub struct RawContext {
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
 pub enum LogLine {
     Normal(NormalLogLine),
     Dangling(DanglingLogLine),
 }
 
+/// How closely a line has to match the header of a log message to start a new one
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum HeaderCheck {
+    /// A severity, a timestamp, a logger and a thread followed by ": "
+    Lenient,
+    /// Also require the logger and thread to be single words, as NSO writes them
+    Strict,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum InputFormat {
+    /// Regular NSO log files
+    Text,
+    /// JSON Lines, as written by "--format json"
+    Jsonl,
+}
+
+impl LogLine {
+    pub fn raw_context(&self) -> Option<&RawContext> {
+        match self {
+            LogLine::Normal(logline) => logline.raw_context.as_ref(),
+            LogLine::Dangling(dangling) => dangling.raw_context.as_ref(),
+        }
+    }
+
+    fn set_raw_context(&mut self, raw_context: RawContext) {
+        match self {
+            LogLine::Normal(logline) => logline.raw_context = Some(raw_context),
+            LogLine::Dangling(dangling) => dangling.raw_context = Some(raw_context),
+        }
+    }
+
+    /// Sets the name of the log file this came from, see `NormalLogLine::file`
+    pub fn set_file(&mut self, file: String) {
+        match self {
+            LogLine::Normal(logline) => logline.file = Some(file),
+            LogLine::Dangling(dangling) => dangling.file = Some(file),
+        }
+    }
+}
+
+impl Display for LogLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogLine::Normal(logline) => logline.fmt(f),
+            LogLine::Dangling(logline) => f.write_str(&logline.text),
+        }
+    }
+}
+
 pub enum ParseSource {
     Stdin(Stdin),
     /// Filename, file
     File(File),
-    /// Filename, tail stdout
-    Tail(ChildStdout),
+    /// A running "tail -f"
+    Tail(Tail),
 }
 
 impl From<Stdin> for ParseSource {
@@ -72,9 +305,9 @@ impl From<File> for ParseSource {
     }
 }
 
-impl From<ChildStdout> for ParseSource {
-    fn from(tail_stdout: ChildStdout) -> Self {
-        Self::Tail(tail_stdout)
+impl From<Tail> for ParseSource {
+    fn from(tail: Tail) -> Self {
+        Self::Tail(tail)
     }
 }
 
@@ -83,7 +316,7 @@ impl Read for ParseSource {
         match self {
             ParseSource::Stdin(stdin) => stdin.read(buf),
             ParseSource::File(file) => file.read(buf),
-            ParseSource::Tail(tail_stdout) => tail_stdout.read(buf),
+            ParseSource::Tail(tail) => tail.read(buf),
         }
     }
 }
@@ -93,33 +326,379 @@ impl AsRawFd for ParseSource {
         match self {
             ParseSource::Stdin(stdin) => stdin.as_raw_fd(),
             ParseSource::File(file) => file.as_raw_fd(),
-            ParseSource::Tail(tail_stdout) => tail_stdout.as_raw_fd(),
+            ParseSource::Tail(tail) => tail.as_raw_fd(),
         }
     }
 }
 
-pub struct LogParser<T: Read + AsRawFd> {
-    lines: Lines<BufReader<T>>,
+/// How long to wait for more input before assuming a log message is complete
+pub const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// The magic bytes at the start of a gzip stream
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// How much to read when peeking at the start of the stream. This must be at least as big as the
+/// buffer of `Stdin`, so reads bypass it. Anything left in that buffer would be invisible to the
+/// timeout reader, which waits on the file descriptor.
+const PEEK_SIZE: usize = 64 * 1024;
+
+impl ParseSource {
+    /// Whether the source is a regular file, as opposed to a stream such as a pipe
+    pub fn is_regular_file(&self) -> bool {
+        match self {
+            ParseSource::File(file) => file.metadata().is_ok_and(|metadata| metadata.is_file()),
+            ParseSource::Stdin(_) | ParseSource::Tail(_) => false,
+        }
+    }
+
+    /// Turns the source into a reader for the parsers, decompressing it if it's gzipped
+    ///
+    /// Reads from streams time out after `READ_TIMEOUT` with a `TimedOut` error, so the parsers
+    /// can tell when they're waiting for more input. Regular files don't need that, they end
+    /// instead.
+    ///
+    pub fn into_reader(mut self) -> Box<dyn Read + Send> {
+        // Peek at the start of the stream. The stream may be shorter than the magic, and errors
+        // are left for the parser to run into on its next read.
+        let mut peeked = vec![0; PEEK_SIZE];
+        let mut len = 0;
+        while len < GZIP_MAGIC.len() {
+            match self.read(&mut peeked[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => break,
+            }
+        }
+        peeked.truncate(len);
+
+        let gzipped = peeked.starts_with(&GZIP_MAGIC);
+        let rest: Box<dyn Read + Send> = match self.is_regular_file() {
+            true => Box::new(self),
+            false => Box::new(self.with_timeout(READ_TIMEOUT)),
+        };
+        let reader = Cursor::new(peeked).chain(rest);
+
+        match gzipped {
+            true => Box::new(MultiGzDecoder::new(reader)),
+            false => Box::new(reader),
+        }
+    }
+}
+
+/// What separates the thread from the message in the NSO log format
+#[derive(Clone, Copy, Debug)]
+pub enum MessageDelimiter {
+    /// ": - ", used by ncs-python-vm-*.log
+    Dash,
+    /// ": ", used by ncs-python-vm.log
+    Plain,
+    /// We don't know which log this is (e.g. STDIN), accept either
+    Either,
+}
+
+impl MessageDelimiter {
+    /// Guesses the delimiter from the name of the log file
+    pub fn for_filename(filename: &str) -> Self {
+        if filename.starts_with("ncs-python-vm-") {
+            MessageDelimiter::Dash
+        } else if filename.starts_with("ncs-python-vm.log") {
+            MessageDelimiter::Plain
+        } else {
+            MessageDelimiter::Either
+        }
+    }
+}
+
+/// How to recognize the first line of a log message and split it into fields
+pub enum LineFormat {
+    /// The built-in NSO log format
+    Nso(MessageDelimiter),
+    /// A user supplied regex with the named groups "sev", "ts", "logger", "thread" and "msg",
+    /// where "thread" is optional. "ts" is parsed using the strftime format `ts_format`.
+    Regex { regex: Regex, ts_format: String },
+}
+
+impl LineFormat {
+    pub fn regex(pattern: &str, ts_format: String) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|err| err.to_string())?;
+
+        for group in ["sev", "ts", "logger", "msg"] {
+            if !regex.capture_names().any(|name| name == Some(group)) {
+                return Err(format!("Parse regex is missing the named group: {}", group));
+            }
+        }
+
+        Ok(Self::Regex { regex, ts_format })
+    }
+
+    /// Tells why `parse` failed for a line
+    fn parse_error(&self, line: &str) -> ParseLineError {
+        let looks_like_header = match self {
+            LineFormat::Nso(_) => parse_severity_from_prefix(line).is_some(),
+            LineFormat::Regex { regex, .. } => regex.is_match(line),
+        };
+
+        match looks_like_header {
+            true => ParseLineError::Malformed,
+            false => ParseLineError::Continuation,
+        }
+    }
+
+    fn parse(&self, line: &str) -> Option<NormalLogLine> {
+        match self {
+            LineFormat::Nso(delimiter) => {
+                parse_line(line, *delimiter).map(NormalLogLineRef::into_owned)
+            }
+            LineFormat::Regex { regex, ts_format } => {
+                let captures = regex.captures(line)?;
+
+                Some(NormalLogLine {
+                    severity: parse_severity(&captures["sev"])?,
+                    datetime: NaiveDateTime::parse_from_str(&captures["ts"], ts_format)
+                        .ok()?
+                        .and_utc(),
+                    logger_name: captures["logger"].to_string(),
+                    thread: captures
+                        .name("thread")
+                        .map(|thread| thread.as_str().to_string())
+                        .unwrap_or_default(),
+                    message: captures["msg"].to_string(),
+                    source: None,
+                    fields: BTreeMap::new(),
+                    file: None,
+                    raw_context: None,
+                    elapsed: None,
+                    raw: None,
+                })
+            }
+        }
+    }
+}
+
+pub struct LogParser<R: BufRead> {
+    reader: R,
+    /// The start of a line that's still being read, kept when a read times out halfway through it
+    partial_line: String,
+    format: LineFormat,
     /// Holds the *next* log message, since we need to read ahead to see if the next line is part
     /// of the current log message
     buffer: Option<NormalLogLine>,
+    /// Group consecutive dangling lines into a single dangling line rather than emitting one per
+    /// line
+    merge_dangling: bool,
+    /// How many raw lines before and after each log message to attach to it, see `raw_context`
+    raw_context: usize,
+    /// Keep the lines each log message was parsed from, see `keep_raw`
+    keep_raw: bool,
+    /// Only let lines with a known severity start a new log message, see `strict`
+    strict: bool,
+    /// How closely a line has to look like a header to start a new log message
+    header_check: HeaderCheck,
+    /// Don't wait for lines that haven't been read yet to see if a message continues, see
+    /// `no_lookahead`
+    no_lookahead: bool,
+    /// Whether the reader has read more input than has been taken from it
+    has_buffered: fn(&R) -> bool,
+    /// The state needed for `raw_context`, unused if it's 0
+    raw: RawHistory,
 }
 
-impl<T: Read + AsRawFd> Iterator for LogParser<T> {
-    type Item = LogLine;
+/// Raw lines kept around for `LogParser::raw_context`
+#[derive(Default)]
+struct RawHistory {
+    /// Recently read lines, `lines[0]` being line number `first`
+    lines: VecDeque<String>,
+    first: usize,
+    /// How many lines have been read in total
+    count: usize,
+    /// The line number of the log message in `LogParser::buffer`
+    buffer_start: usize,
+    /// Log messages waiting for the lines after them to be read, with their line numbers
+    pending: VecDeque<(LogLine, Range<usize>)>,
+    /// Set when we're waiting for input with messages pending, they're sent on without waiting
+    /// for the rest of their context
+    idle: bool,
+    ended: bool,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut log_message: NormalLogLine = if let Some(log_message) = self.buffer.take() {
-            log_message
+impl<R: BufRead> LogParser<R> {
+    /// Parses log messages from any reader. Unlike `parse_log` there's no gzip detection, and the
+    /// reader is expected to end rather than time out while waiting for more input.
+    pub fn from_reader(reader: R, format: LineFormat) -> Self {
+        Self {
+            reader,
+            partial_line: String::new(),
+            format,
+            buffer: None,
+            merge_dangling: false,
+            raw_context: 0,
+            keep_raw: false,
+            strict: false,
+            header_check: HeaderCheck::Lenient,
+            no_lookahead: false,
+            // Without knowing better, assume there's more input ready
+            has_buffered: |_| true,
+            raw: RawHistory::default(),
+        }
+    }
+
+    pub fn merge_dangling(mut self, merge_dangling: bool) -> Self {
+        self.merge_dangling = merge_dangling;
+        self
+    }
+
+    /// Attach up to `lines` raw lines from before and after each log message to it, for seeing
+    /// how the input was grouped into messages
+    pub fn raw_context(mut self, lines: usize) -> Self {
+        self.raw_context = lines;
+        self
+    }
+
+    /// Keep the lines each log message was parsed from in `NormalLogLine::raw`
+    pub fn keep_raw(mut self, keep_raw: bool) -> Self {
+        self.keep_raw = keep_raw;
+        self
+    }
+
+    /// Only let lines with a known severity start a new log message, anything else is a
+    /// continuation of the previous one. Unknown severities are normally accepted to support
+    /// newer NSO versions, but embedded XML such as "<DATA> ..." can look like one.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn header_check(mut self, header_check: HeaderCheck) -> Self {
+        self.header_check = header_check;
+        self
+    }
+
+    /// Only group lines into a message if they've already been read along with its first line,
+    /// rather than waiting the `READ_TIMEOUT` for more. This emits messages as soon as they're
+    /// read, but a multi-line message that arrives in pieces is split.
+    pub fn no_lookahead(mut self, no_lookahead: bool) -> Self {
+        self.no_lookahead = no_lookahead;
+        self
+    }
+
+    /// Parses a line as the start of a new log message
+    ///
+    /// Lines rejected by `strict` or `header_check` are continuations, that's what those options
+    /// are for.
+    ///
+    fn parse(&self, line: &str) -> Result<NormalLogLine, ParseLineError> {
+        let single_word = |text: &str| !text.is_empty() && !text.contains(char::is_whitespace);
+
+        let mut log_message = self
+            .format
+            .parse(line)
+            .ok_or_else(|| self.format.parse_error(line))?;
+
+        let accepted = !(self.strict && log_message.severity.is_unknown())
+            && match self.header_check {
+                HeaderCheck::Lenient => true,
+                HeaderCheck::Strict => {
+                    single_word(&log_message.logger_name) && single_word(&log_message.thread)
+                }
+            };
+        if !accepted {
+            return Err(ParseLineError::Continuation);
+        }
+
+        if self.keep_raw {
+            log_message.raw = Some(line.to_string());
+        }
+        Ok(log_message)
+    }
+
+    /// Reads the next line, without the line ending, like `BufRead::lines`
+    fn read_line(&mut self) -> Option<std::io::Result<String>> {
+        match self.reader.read_line(&mut self.partial_line) {
+            Ok(0) if self.partial_line.is_empty() => None,
+            Ok(_) => {
+                let mut line = std::mem::take(&mut self.partial_line);
+                if line.ends_with('\n') {
+                    line.pop();
+                }
+                // CRLF line endings, including on a last line that has no line feed
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    fn next_line(&mut self) -> Option<std::io::Result<String>> {
+        let line = self.read_line();
+
+        if let (Some(Ok(line)), true) = (&line, self.raw_context > 0) {
+            self.raw.lines.push_back(line.clone());
+            self.raw.count += 1;
+        }
+
+        line
+    }
+
+    /// The line number of the next message to be returned by `next_message`
+    fn next_message_start(&self) -> usize {
+        match self.buffer {
+            Some(_) => self.raw.buffer_start,
+            None => self.raw.count,
+        }
+    }
+
+    /// Sends on the oldest pending message with the raw lines around it
+    fn pop_pending(&mut self) -> Option<LogLine> {
+        let (mut logline, range) = self.raw.pending.pop_front()?;
+        let line = |number: usize| self.raw.lines[number - self.raw.first].clone();
+
+        let before = (range.start.saturating_sub(self.raw_context)..range.start).map(line);
+        let after = (range.end..(range.end + self.raw_context).min(self.raw.count)).map(line);
+        logline.set_raw_context(RawContext {
+            before: before.collect(),
+            after: after.collect(),
+        });
+
+        // Forget the lines that no message needs anymore
+        let needed = match self.raw.pending.front() {
+            Some((_, range)) => range.start,
+            None => self.next_message_start(),
+        }
+        .saturating_sub(self.raw_context);
+        while self.raw.first < needed && self.raw.lines.pop_front().is_some() {
+            self.raw.first += 1;
+        }
+
+        Some(logline)
+    }
+
+    fn next_message(&mut self) -> Option<LogLine> {
+        let mut logline: LogLine = if let Some(log_message) = self.buffer.take() {
+            LogLine::Normal(log_message)
         } else {
             let line = loop {
-                match self.lines.next() {
+                match self.next_line() {
                     Some(Ok(line)) => break line,
 
+                    // Messages waiting for their raw context shouldn't wait for more input
+                    Some(Err(e))
+                        if e.kind() == std::io::ErrorKind::TimedOut
+                            && !self.raw.pending.is_empty() =>
+                    {
+                        self.raw.idle = true;
+                        return None;
+                    }
+
                     // Do nothing, wait for the next log line to be emitted. This can happen while
                     // tailing a file or while parsing from STDIN.
                     Some(Err(e)) if e.kind() == std::io::ErrorKind::TimedOut => {}
 
+                    // A signal interrupted the wait for input, we're shutting down
+                    Some(Err(_)) if signals::interrupted() => return None,
+
                     // Let's panic, just to find out which errors can happen here
                     Some(Err(e)) => panic!("Fatal error: {}", e),
 
@@ -128,10 +707,19 @@ impl<T: Read + AsRawFd> Iterator for LogParser<T> {
                 };
             };
 
-            match line.parse::<NormalLogLine>() {
-                Ok(log_message) => log_message,
-                Err(_) => {
-                    return Some(LogLine::Dangling(DanglingLogLine { text: line }));
+            match self.parse(&line) {
+                Ok(log_message) => LogLine::Normal(log_message),
+                Err(error) => {
+                    let dangling = LogLine::Dangling(DanglingLogLine {
+                        parse_error: Some(error),
+                        ..DanglingLogLine::new(line)
+                    });
+
+                    // Read ahead for more dangling lines, just like for a log message
+                    if !self.merge_dangling {
+                        return Some(dangling);
+                    }
+                    dangling
                 }
             }
         };
@@ -139,104 +727,211 @@ impl<T: Read + AsRawFd> Iterator for LogParser<T> {
         // Read ahead to grab any lines that belong to the same log message. (Any line that can't
         // be parsed as a new log message.)
         loop {
-            let next_line = match self.lines.next() {
+            if self.no_lookahead
+                && self.partial_line.is_empty()
+                && !(self.has_buffered)(&self.reader)
+            {
+                return Some(logline);
+            }
+
+            let next_line = match self.next_line() {
                 Some(Ok(line)) => line,
 
                 // If we time out, that means we're waiting for new log messages. The means there
                 // are definitely no more lines associated with the current log message.
-                Some(Err(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
-                    return Some(LogLine::Normal(log_message))
-                }
+                Some(Err(e)) if e.kind() == std::io::ErrorKind::TimedOut => return Some(logline),
+                Some(Err(_)) if signals::interrupted() => return Some(logline),
 
                 // Let's panic, just to find out which errors can happen here
                 Some(Err(e)) => panic!("Fatal error: {}", e),
 
                 // End of iterator
-                None => return Some(LogLine::Normal(log_message)),
+                None => return Some(logline),
             };
 
-            match next_line.parse::<NormalLogLine>() {
+            match self.parse(&next_line) {
                 Ok(next_log_message) => {
                     self.buffer = Some(next_log_message);
-                    return Some(LogLine::Normal(log_message));
+                    self.raw.buffer_start = self.raw.count.saturating_sub(1);
+                    return Some(logline);
                 }
-                Err(_) => {
-                    // Add next_line as a new line to the end of log_message.message
-                    log_message.message.push('\n');
-                    log_message.message.push_str(&next_line);
+                Err(error) => {
+                    // Add next_line as a new line to the end of the message
+                    let text = match &mut logline {
+                        LogLine::Normal(log_message) => {
+                            if let Some(raw) = &mut log_message.raw {
+                                raw.push('\n');
+                                raw.push_str(&next_line);
+                            }
+                            &mut log_message.message
+                        }
+                        LogLine::Dangling(dangling) => {
+                            if error == ParseLineError::Malformed {
+                                dangling.parse_error = Some(error);
+                            }
+                            &mut dangling.text
+                        }
+                    };
+                    text.push('\n');
+                    text.push_str(&next_line);
                 }
             }
         }
     }
 }
 
-pub fn parse_log(source: ParseSource) -> LogParser<impl Read + AsRawFd> {
+impl<R: BufRead> Iterator for LogParser<R> {
+    type Item = LogLine;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.raw_context == 0 {
+            return self.next_message();
+        }
+
+        // Hold on to each message until the raw lines after it have been read
+        loop {
+            if let Some((_, range)) = self.raw.pending.front() {
+                if self.raw.ended || self.raw.idle || self.raw.count >= range.end + self.raw_context
+                {
+                    return self.pop_pending();
+                }
+            }
+
+            if self.raw.ended {
+                return None;
+            }
+
+            self.raw.idle = false;
+            let start = self.next_message_start();
+
+            match self.next_message() {
+                Some(logline) => {
+                    let end = self.next_message_start();
+                    self.raw.pending.push_back((logline, start..end));
+                }
+                None if self.raw.idle => {}
+                None => self.raw.ended = true,
+            }
+        }
+    }
+}
+
+pub fn parse_log(source: ParseSource, format: LineFormat) -> LogParser<impl BufRead> {
+    // Reading ahead in a regular file never has to wait, so that's always worth it
+    let has_buffered: fn(&BufReader<Box<dyn Read + Send>>) -> bool = match source.is_regular_file()
+    {
+        true => |_| true,
+        false => |reader| !reader.buffer().is_empty(),
+    };
+
     LogParser {
-        lines: BufReader::new(source.with_timeout(Duration::from_millis(10))).lines(),
-        buffer: None,
+        has_buffered,
+        ..LogParser::from_reader(BufReader::new(source.into_reader()), format)
     }
 }
 
-fn parse_line(line: &str) -> Option<NormalLogLine> {
-    if line.chars().next()? != '<' {
-        return None;
+/// Parses log messages that are already in memory
+pub fn parse_str(input: &str, format: LineFormat) -> LogParser<&[u8]> {
+    LogParser::from_reader(input.as_bytes(), format)
+}
+
+/// Parses input where every line stands on its own, so there's no need to read ahead
+pub struct SingleLineParser<T: Read> {
+    lines: Lines<BufReader<T>>,
+    parse: fn(String) -> LogLine,
+}
+
+impl<T: Read> Iterator for SingleLineParser<T> {
+    type Item = LogLine;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = loop {
+            match self.lines.next() {
+                Some(Ok(line)) => break line,
+
+                // Every line is a complete log message, so there's nothing to flush, just wait for
+                // the next one
+                Some(Err(e)) if e.kind() == std::io::ErrorKind::TimedOut => {}
+
+                // A signal interrupted the wait for input, we're shutting down
+                Some(Err(_)) if signals::interrupted() => return None,
+
+                // Let's panic, just to find out which errors can happen here
+                Some(Err(e)) => panic!("Fatal error: {}", e),
+
+                // End of iterator
+                None => return None,
+            };
+        };
+
+        // Lines strips CRLF, but not a carriage return on a last line that has no line feed
+        if line.ends_with('\r') {
+            line.pop();
+        }
+
+        Some((self.parse)(line))
+    }
+}
+
+/// Parses log lines previously exported as JSON Lines (one JSON object per line)
+pub fn parse_jsonl(source: ParseSource) -> SingleLineParser<impl Read> {
+    SingleLineParser {
+        lines: BufReader::new(source.into_reader()).lines(),
+        parse: |line| match serde_json::from_str::<LogLine>(&line) {
+            Ok(logline) => logline,
+            Err(_) => LogLine::Dangling(DanglingLogLine::new(line)),
+        },
     }
+}
 
-    let severity_start = 1;
-    let severity_end = line.char_indices().find(|(_, x)| *x == '>')?.0;
+/// Doesn't parse anything, every line is passed through as a dangling line
+pub fn parse_raw(source: ParseSource) -> SingleLineParser<impl Read> {
+    SingleLineParser {
+        lines: BufReader::new(source.into_reader()).lines(),
+        parse: |line| LogLine::Dangling(DanglingLogLine::new(line)),
+    }
+}
 
-    let severity = match &line[severity_start..severity_end] {
-        "DEBUG" => Severity::Debug,
-        "INFO" => Severity::Info,
-        "WARN" => Severity::Warning,
-        "WARNING" => Severity::Warning,
-        "ERR" => Severity::Error,
-        "ERROR" => Severity::Error,
-        "CRIT" => Severity::Critical,
-        "CRITICAL" => Severity::Critical,
-        _ => return None,
-    };
+fn parse_line(line: &str, delimiter: MessageDelimiter) -> Option<NormalLogLineRef<'_>> {
+    let (severity, severity_end) = parse_severity_from_prefix(line)?;
+
+    // All slicing uses get() rather than indexing, so a truncated or odd line just fails to
+    // parse as a log message instead of panicking
 
     let date_start = severity_end + 2;
-    let date_end = date_start
-        + line[date_start..]
-            .char_indices()
-            .find(|(_, x)| *x == ' ')?
-            .0;
+    let date_end = date_start + line.get(date_start..)?.find(' ')?;
 
-    let datetime =
-        NaiveDateTime::parse_from_str(&line[date_start..date_end], "%d-%b-%Y::%H:%M:%S%.3f")
-            .ok()?
-            .and_utc();
+    let datetime = parse_datetime(line.get(date_start..date_end)?)?.and_utc();
 
     let logger_name_start = date_end + 1;
-    let logger_name_end = logger_name_start
-        + line[logger_name_start..]
-            .char_indices()
-            .find(|(_, x)| *x == ' ')?
-            .0;
+    let logger_name_end = logger_name_start + line.get(logger_name_start..)?.find(' ')?;
 
-    let logger_name = line[logger_name_start..logger_name_end].to_string();
+    let logger_name = line.get(logger_name_start..logger_name_end)?;
 
     let thread_start = logger_name_end + 1;
-    let thread_end = thread_start + line[thread_start..].find(": ")?;
+    let thread_end = thread_start + line.get(thread_start..)?.find(": ")?;
 
-    let thread = line[thread_start..thread_end].to_string();
-    let mut message_start = thread_end + 2;
+    let thread = line.get(thread_start..thread_end)?;
+    let rest = line.get(thread_end + 2..)?;
 
     // ncs-python-vm-*.log (for some reason) uses ": - " as the message delimiter, but
-    // ncs-python-vm.log doesn't
-    if &line[message_start..message_start + 2] == "- " {
-        message_start += 2;
-    }
+    // ncs-python-vm.log doesn't. Some versions pad it with an extra space, as in ":  - ". When we
+    // know the log uses ": ", a message that happens to start with "- " is left alone.
+    let message = match delimiter {
+        MessageDelimiter::Plain => rest,
+        MessageDelimiter::Dash | MessageDelimiter::Either => {
+            match rest.trim_start_matches(' ').strip_prefix("- ") {
+                Some(message) => message,
+                None => rest,
+            }
+        }
+    };
 
-    if message_start >= line.chars().count() {
+    if message.is_empty() {
         return None;
     }
 
-    let message = line[message_start..].to_string();
-
-    Some(NormalLogLine {
+    Some(NormalLogLineRef {
         severity,
         datetime,
         logger_name,
@@ -244,3 +939,687 @@ fn parse_line(line: &str) -> Option<NormalLogLine> {
         message,
     })
 }
+
+/// Parses an NSO timestamp such as "02-Jan-2024::15:04:05.123"
+///
+/// Parsing with a format string is a large part of the time spent parsing a log, so the usual
+/// shape is parsed by hand. Anything else falls back to chrono, which gives the same result.
+///
+pub fn parse_datetime(input: &str) -> Option<NaiveDateTime> {
+    const MONTHS: [&[u8; 3]; 12] = [
+        b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov",
+        b"Dec",
+    ];
+
+    let fast = || {
+        let bytes = input.as_bytes();
+        if bytes.len() != 25 || &bytes[11..13] != b"::" {
+            return None;
+        }
+        let number = |range: Range<usize>| -> Option<u32> {
+            bytes[range].iter().try_fold(0, |number, &byte| {
+                byte.is_ascii_digit()
+                    .then(|| number * 10 + u32::from(byte - b'0'))
+            })
+        };
+        let separators = [(2, b'-'), (6, b'-'), (15, b':'), (18, b':'), (21, b'.')];
+        if separators
+            .iter()
+            .any(|&(i, separator)| bytes[i] != separator)
+        {
+            return None;
+        }
+
+        let month = MONTHS.iter().position(|month| &bytes[3..6] == *month)? as u32 + 1;
+        NaiveDate::from_ymd_opt(number(7..11)? as i32, month, number(0..2)?)?.and_hms_milli_opt(
+            number(13..15)?,
+            number(16..18)?,
+            number(19..21)?,
+            number(22..25)?,
+        )
+    };
+
+    fast().or_else(|| NaiveDateTime::parse_from_str(input, "%d-%b-%Y::%H:%M:%S%.3f").ok())
+}
+
+/// The severity at the start of a line, without parsing the rest of it
+pub fn line_severity(line: &str) -> Option<Severity> {
+    let (token, _) = line.strip_prefix('<')?.split_once('>')?;
+    parse_severity(token)
+}
+
+/// Parses the "<SEVERITY> " at the start of a line, returns the severity and the index of the ">"
+///
+/// This is the first thing checked for every line, so it fails as early as it can. Continuation
+/// lines such as tracebacks and XML are rejected without looking further than the first character
+/// that can't be part of a severity, rather than searching the whole line for a ">".
+///
+fn parse_severity_from_prefix(line: &str) -> Option<(Severity, usize)> {
+    let bytes = line.as_bytes();
+    if bytes.first() != Some(&b'<') {
+        return None;
+    }
+
+    // Severities are all uppercase, which is also all that's allowed for unknown ones
+    let severity_end = 1 + bytes[1..].iter().position(|b| !b.is_ascii_uppercase())?;
+    if bytes.get(severity_end..severity_end + 2) != Some(b"> ") {
+        return None;
+    }
+
+    Some((parse_severity(&line[1..severity_end])?, severity_end))
+}
+
+/// The severity tokens of the NSO log format, with the severity each means
+const SEVERITY_TOKENS: &[(&str, Severity)] = &[
+    ("TRACE", Severity::Trace),
+    ("DEBUG", Severity::Debug),
+    ("INFO", Severity::Info),
+    ("WARN", Severity::Warning),
+    ("WARNING", Severity::Warning),
+    ("ERR", Severity::Error),
+    ("ERROR", Severity::Error),
+    ("CRIT", Severity::Critical),
+    ("CRITICAL", Severity::Critical),
+];
+
+fn parse_severity(token: &str) -> Option<Severity> {
+    match SEVERITY_TOKENS.iter().find(|(known, _)| *known == token) {
+        Some((_, severity)) => Some(severity.clone()),
+        // Keep the structure of lines with levels from newer NSO versions, but don't let just
+        // anything in angle brackets through
+        None if !token.is_empty() && token.chars().all(|c| c.is_ascii_uppercase()) => {
+            Some(Severity::Unknown(token.to_string()))
+        }
+        None => None,
+    }
+}
+
+/// Describes the log line format the parser expects, for "--help-format"
+///
+/// The severities are listed from the same table the parser uses.
+///
+pub fn format_help() -> String {
+    let severities = SEVERITY_TOKENS
+        .iter()
+        .map(|(token, _)| *token)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "\
+A log message starts with a line like this:
+
+    <SEVERITY> DD-Mon-YYYY::HH:MM:SS.mmm LOGGER THREAD: MESSAGE
+
+    <INFO> 02-Jan-2024::15:04:05.123 ncs-python-vm-cfs Thread-12: - Service created
+
+SEVERITY   One of {severities}.
+           Other uppercase words are kept as unknown severities. It must be followed
+           by a single space.
+TIMESTAMP  The day, English month abbreviation and year, \"::\", then the time with
+           milliseconds. Times are taken to be UTC.
+LOGGER     Everything up to the next space.
+THREAD     Everything up to the next \": \", so it may contain spaces.
+MESSAGE    The rest of the line, which can't be empty. The ncs-python-vm-*.log files put
+           \"- \" in front of it, which is removed. In ncs-python-vm.log the message is taken
+           as-is, and for other files (or STDIN) a leading \"- \" is removed if there is one.
+
+Every following line that doesn't start a new log message belongs to the message above it.
+Lines that can't be placed in a log message this way, e.g. at the start of the input, are
+shown as they are, as dangling lines.
+
+With --merge-continuations-aggressively, lines with unknown severities don't start new log
+messages. With \"--header-check strict\", neither do lines where LOGGER or THREAD is empty or
+has spaces in it. Use --parse-regex for logs in other formats.
+"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::*;
+
+    fn parse(text: &str) -> Vec<LogLine> {
+        parse_str(text, LineFormat::Nso(MessageDelimiter::Either)).collect()
+    }
+
+    fn normal(logline: &LogLine) -> &NormalLogLine {
+        match logline {
+            LogLine::Normal(logline) => logline,
+            LogLine::Dangling(dangling) => panic!("Dangling line: {:?}", dangling.text),
+        }
+    }
+
+    #[test]
+    fn parses_a_log_message() {
+        let loglines =
+            parse("<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Starting up\n");
+        let logline = normal(&loglines[0]);
+
+        assert_eq!(loglines.len(), 1);
+        assert_eq!(logline.severity, Severity::Info);
+        assert_eq!(
+            logline.datetime,
+            Utc.with_ymd_and_hms(2024, 1, 2, 15, 4, 5).unwrap()
+                + chrono::TimeDelta::milliseconds(123)
+        );
+        assert_eq!(logline.logger_name, "my-logger");
+        assert_eq!(logline.thread, "MainThread");
+        assert_eq!(logline.message, "Starting up");
+    }
+
+    /// The fields that survive a round trip through the NSO format
+    fn fields(logline: &NormalLogLine) -> (&Severity, DateTime<Utc>, &str, &str, &str) {
+        (
+            &logline.severity,
+            logline.datetime,
+            &logline.logger_name,
+            &logline.thread,
+            &logline.message,
+        )
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let text = "\
+<WARNING> 02-Jan-2024::15:04:05.123 my-logger Thread-1: - Careful
+<ERROR> 02-Jan-2024::15:04:06.000 other ThreadPool-2: - Boom
+Traceback line 1
+  line 2
+<FOO> 02-Jan-2024::15:04:07.999 other MainThread: - Unknown severity
+";
+        for logline in parse(text) {
+            let displayed = logline.to_string();
+            let reparsed = parse(&displayed);
+
+            assert_eq!(reparsed.len(), 1, "{:?}", displayed);
+            assert_eq!(fields(normal(&reparsed[0])), fields(normal(&logline)));
+        }
+    }
+
+    #[test]
+    fn display_is_the_nso_format() {
+        let line = "<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: Starting up";
+
+        assert_eq!(parse(line)[0].to_string(), line);
+    }
+
+    #[test]
+    fn parse_datetime_agrees_with_chrono() {
+        let inputs = [
+            "02-Jan-2024::15:04:05.123",
+            "31-Dec-1999::23:59:59.999",
+            "29-Feb-2024::00:00:00.000",
+            // Not a leap year
+            "29-Feb-2023::00:00:00.000",
+            "02-jan-2024::15:04:05.123",
+            "02-JAN-2024::15:04:05.123",
+            "32-Jan-2024::15:04:05.123",
+            "02-Jan-2024::24:04:05.123",
+            "02-Jan-2024::15:60:05.123",
+            "02-Jan-2024::15:04:60.123",
+            // Fractional seconds that aren't milliseconds
+            "02-Jan-2024::15:04:05.12",
+            "02-Jan-2024::15:04:05.1234",
+            "02-Jan-2024::15:04:05",
+            // Bad digits
+            "0x-Jan-2024::15:04:05.123",
+            "02-Jan-2O24::15:04:05.123",
+            "02-Jan-2024::15:04:05.12a",
+            "02-Jan-2024::+5:04:05.123",
+            "02-Jan-2024:15:04:05.123",
+            "02-Jan-2024::15:04:05,123",
+            "2-Jan-2024::15:04:05.123",
+            "",
+        ];
+
+        for input in inputs {
+            assert_eq!(
+                parse_datetime(input),
+                NaiveDateTime::parse_from_str(input, "%d-%b-%Y::%H:%M:%S%.3f").ok(),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    const CUSTOM_REGEX: &str =
+        r"^(?P<ts>\d{4}-\d\d-\d\d \d\d:\d\d:\d\d) \[(?P<sev>\w+)\] (?P<logger>\S+): (?P<msg>.*)$";
+
+    #[test]
+    fn custom_format() {
+        let format = LineFormat::regex(CUSTOM_REGEX, "%Y-%m-%d %H:%M:%S".to_string()).unwrap();
+        let text = "2024-01-02 15:04:05 [WARNING] my-logger: Careful\n  more\nnot a header\n";
+        let loglines: Vec<LogLine> = parse_str(text, format).collect();
+        let logline = normal(&loglines[0]);
+
+        assert_eq!(loglines.len(), 1);
+        assert_eq!(logline.severity, Severity::Warning);
+        assert_eq!(
+            logline.datetime,
+            Utc.with_ymd_and_hms(2024, 1, 2, 15, 4, 5).unwrap()
+        );
+        assert_eq!(logline.logger_name, "my-logger");
+        assert_eq!(logline.thread, "");
+        assert_eq!(logline.message, "Careful\n  more\nnot a header");
+    }
+
+    #[test]
+    fn custom_format_with_thread() {
+        let regex = r"^(?P<sev>\w+) (?P<ts>\S+) (?P<logger>\S+) (?P<thread>\S+) (?P<msg>.*)$";
+        let format = LineFormat::regex(regex, "%Y-%m-%dT%H:%M:%S".to_string()).unwrap();
+        let loglines: Vec<LogLine> =
+            parse_str("ERROR 2024-01-02T15:04:05 a t1 Boom\n", format).collect();
+
+        assert_eq!(normal(&loglines[0]).thread, "t1");
+        assert_eq!(normal(&loglines[0]).message, "Boom");
+    }
+
+    #[test]
+    fn custom_format_with_a_bad_timestamp_is_dangling() {
+        let format = LineFormat::regex(CUSTOM_REGEX, "%d/%m/%Y %H:%M:%S".to_string()).unwrap();
+        let loglines: Vec<LogLine> =
+            parse_str("2024-01-02 15:04:05 [INFO] a: Hi\n", format).collect();
+
+        assert!(matches!(loglines[..], [LogLine::Dangling(_)]));
+    }
+
+    #[test]
+    fn custom_format_needs_the_named_groups() {
+        let error = LineFormat::regex(r"(?P<sev>\w+) (?P<ts>\S+) (?P<msg>.*)", String::new())
+            .err()
+            .unwrap();
+
+        assert_eq!(error, "Parse regex is missing the named group: logger");
+        assert!(LineFormat::regex("(", String::new()).is_err());
+    }
+
+    fn extract_source(message: &str) -> (Option<String>, String) {
+        let text = format!("<INFO> 02-Jan-2024::15:04:05.123 a b: - {}\n", message);
+        let LogLine::Normal(mut logline) = parse(&text).remove(0) else {
+            panic!("Not parsed: {:?}", text);
+        };
+        logline.extract_source();
+        (logline.source, logline.message)
+    }
+
+    #[test]
+    fn extracts_the_source() {
+        assert_eq!(
+            extract_source("[my_service.main:42] Starting"),
+            (
+                Some("my_service.main:42".to_string()),
+                "Starting".to_string()
+            )
+        );
+        assert_eq!(
+            extract_source("[l3vpn-svc_2:7] Multi\nline"),
+            (Some("l3vpn-svc_2:7".to_string()), "Multi\nline".to_string())
+        );
+    }
+
+    #[test]
+    fn leaves_messages_without_a_source_alone() {
+        for message in [
+            "Starting",
+            "[my_service.main:42]Starting",
+            "[my_service.main] Starting",
+            "[my_service.main:4x] Starting",
+            "[:42] Starting",
+            "[my service:42] Starting",
+            "[my_service.main:] Starting",
+            "Starting [my_service.main:42] later",
+            "[a:1",
+        ] {
+            assert_eq!(extract_source(message), (None, message.to_string()));
+        }
+    }
+
+    fn message(line: &str, delimiter: MessageDelimiter) -> Option<&str> {
+        NormalLogLineRef::parse(line, delimiter).map(|logline| logline.message)
+    }
+
+    #[test]
+    fn message_delimiters() {
+        let dash = "<INFO> 02-Jan-2024::15:04:05.123 a b: - Hi";
+        let double_space = "<INFO> 02-Jan-2024::15:04:05.123 a b:  - Hi";
+        let plain = "<INFO> 02-Jan-2024::15:04:05.123 a b: Hi";
+        let plain_dash = "<INFO> 02-Jan-2024::15:04:05.123 a b: - list item";
+
+        // ncs-python-vm-*.log
+        assert_eq!(message(dash, MessageDelimiter::Dash), Some("Hi"));
+        assert_eq!(message(double_space, MessageDelimiter::Dash), Some("Hi"));
+        assert_eq!(message(plain, MessageDelimiter::Dash), Some("Hi"));
+
+        // ncs-python-vm.log, where a message starting with "- " is left alone
+        assert_eq!(message(plain, MessageDelimiter::Plain), Some("Hi"));
+        assert_eq!(
+            message(plain_dash, MessageDelimiter::Plain),
+            Some("- list item")
+        );
+
+        assert_eq!(message(dash, MessageDelimiter::Either), Some("Hi"));
+        assert_eq!(message(double_space, MessageDelimiter::Either), Some("Hi"));
+        assert_eq!(message(plain, MessageDelimiter::Either), Some("Hi"));
+    }
+
+    #[test]
+    fn delimiter_from_filename() {
+        assert!(matches!(
+            MessageDelimiter::for_filename("ncs-python-vm-l3vpn.log"),
+            MessageDelimiter::Dash
+        ));
+        assert!(matches!(
+            MessageDelimiter::for_filename("ncs-python-vm.log"),
+            MessageDelimiter::Plain
+        ));
+        assert!(matches!(
+            MessageDelimiter::for_filename("ncs-java-vm.log"),
+            MessageDelimiter::Either
+        ));
+    }
+
+    #[test]
+    fn truncated_lines_dont_panic() {
+        let line = "<INFO> 02-Jan-2024::15:04:05.123 a b: - Hi ✓";
+        for end in (0..line.len()).filter(|&end| line.is_char_boundary(end)) {
+            for delimiter in [MessageDelimiter::Dash, MessageDelimiter::Plain] {
+                let _ = message(&line[..end], delimiter);
+            }
+        }
+
+        assert_eq!(
+            message(
+                "<INFO> 02-Jan-2024::15:04:05.123 a b: -",
+                MessageDelimiter::Dash
+            ),
+            Some("-")
+        );
+        assert_eq!(
+            message(
+                "<INFO> 02-Jan-2024::15:04:05.123 a b: ",
+                MessageDelimiter::Dash
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn trace_severity() {
+        let loglines = parse("<TRACE> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Tracing\n");
+
+        assert_eq!(normal(&loglines[0]).severity, Severity::Trace);
+        assert_eq!(normal(&loglines[0]).message, "Tracing");
+        assert_eq!(line_severity("<TRACE> whatever"), Some(Severity::Trace));
+    }
+
+    #[test]
+    fn trace_is_the_lowest_severity() {
+        let mut severities = [
+            Severity::Critical,
+            Severity::Info,
+            Severity::Trace,
+            Severity::Error,
+            Severity::Debug,
+            Severity::Warning,
+        ];
+        severities.sort();
+
+        assert_eq!(severities[0], Severity::Trace);
+        assert_eq!(severities[1], Severity::Debug);
+    }
+
+    fn messages(loglines: &[LogLine]) -> Vec<String> {
+        loglines
+            .iter()
+            .map(|logline| normal(logline).message.clone())
+            .collect()
+    }
+
+    const RPC_REPLY: &str = "\
+<DEBUG> 02-Jan-2024::15:04:05.123 netconf MainThread: - Reply:
+<rpc-reply message-id=\"1\" xmlns=\"urn:ietf:params:xml:ns:netconf:base:1.0\">
+<DATA> 02-Jan-2024::15:04:05.123 looks like: a header</DATA>
+</rpc-reply>
+<INFO> 02-Jan-2024::15:04:06.000 netconf MainThread: - Done
+";
+
+    #[test]
+    fn xml_continuations() {
+        let loglines = parse(RPC_REPLY);
+
+        // The unknown severity "DATA" is accepted, so that line starts a new message
+        assert_eq!(loglines.len(), 3);
+        assert!(normal(&loglines[0]).message.ends_with("base:1.0\">"));
+        assert_eq!(
+            normal(&loglines[1]).severity,
+            Severity::Unknown("DATA".to_string())
+        );
+    }
+
+    #[test]
+    fn xml_continuations_merged_aggressively() {
+        let loglines: Vec<LogLine> =
+            parse_str(RPC_REPLY, LineFormat::Nso(MessageDelimiter::Either))
+                .strict(true)
+                .collect();
+
+        assert_eq!(
+            messages(&loglines),
+            [
+                "Reply:
+<rpc-reply message-id=\"1\" xmlns=\"urn:ietf:params:xml:ns:netconf:base:1.0\">
+<DATA> 02-Jan-2024::15:04:05.123 looks like: a header</DATA>
+</rpc-reply>",
+                "Done"
+            ]
+        );
+    }
+
+    const PSEUDO_HEADER: &str = "\
+<ERROR> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Output of the device:
+<INFO> 02-Jan-2024::15:04:05.123 interface GigabitEthernet0/0/1 is: up
+<WARNING> 02-Jan-2024::15:04:05.123 : - empty logger
+<INFO> 02-Jan-2024::15:04:06.000 my-logger MainThread: - Done
+";
+
+    fn parse_with(text: &str, header_check: HeaderCheck) -> Vec<LogLine> {
+        parse_str(text, LineFormat::Nso(MessageDelimiter::Either))
+            .header_check(header_check)
+            .collect()
+    }
+
+    #[test]
+    fn pseudo_headers_split_leniently() {
+        let loglines = parse_with(PSEUDO_HEADER, HeaderCheck::Lenient);
+
+        assert_eq!(loglines.len(), 3);
+        assert_eq!(normal(&loglines[1]).thread, "GigabitEthernet0/0/1 is");
+    }
+
+    #[test]
+    fn pseudo_headers_are_continuations_when_strict() {
+        let loglines = parse_with(PSEUDO_HEADER, HeaderCheck::Strict);
+        let lines: Vec<&str> = PSEUDO_HEADER.lines().collect();
+
+        assert_eq!(
+            messages(&loglines),
+            [
+                format!("Output of the device:\n{}\n{}", lines[1], lines[2]),
+                "Done".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn real_headers_pass_the_strict_check() {
+        let text = "<INFO> 02-Jan-2024::15:04:05.123 ncs-python-vm-l3vpn Thread-12: - Hi\n";
+
+        assert_eq!(messages(&parse_with(text, HeaderCheck::Strict)), ["Hi"]);
+    }
+
+    /// Input arriving in chunks, counting how many have been read
+    struct Chunks<'a> {
+        chunks: std::slice::Iter<'a, &'a str>,
+        read: &'a std::cell::Cell<usize>,
+    }
+
+    impl Read for Chunks<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some(chunk) = self.chunks.next() else {
+                return Ok(0);
+            };
+            self.read.set(self.read.get() + 1);
+            buf[..chunk.len()].copy_from_slice(chunk.as_bytes());
+            Ok(chunk.len())
+        }
+    }
+
+    /// The first log message, and how many chunks had been read by the time it was emitted
+    fn first_message(chunks: &[&str], no_lookahead: bool) -> (String, usize) {
+        let read = std::cell::Cell::new(0);
+        let reader = BufReader::new(Chunks {
+            chunks: chunks.iter(),
+            read: &read,
+        });
+        let mut parser = LogParser {
+            has_buffered: |reader: &BufReader<Chunks>| !reader.buffer().is_empty(),
+            ..LogParser::from_reader(reader, LineFormat::Nso(MessageDelimiter::Either))
+        }
+        .no_lookahead(no_lookahead);
+
+        let message = normal(&parser.next().unwrap()).message.clone();
+        (message, read.get())
+    }
+
+    const CHUNKS: &[&str] = &[
+        "<INFO> 02-Jan-2024::15:04:05.123 a b: - First\n",
+        "  written later\n<INFO> 02-Jan-2024::15:04:06.000 a b: - Second\n",
+    ];
+
+    #[test]
+    fn lookahead_waits_for_the_next_line() {
+        assert_eq!(
+            first_message(CHUNKS, false),
+            ("First\n  written later".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn no_lookahead_emits_what_has_been_read() {
+        assert_eq!(first_message(CHUNKS, true), ("First".to_string(), 1));
+    }
+
+    #[test]
+    fn no_lookahead_groups_lines_read_together() {
+        let chunks = ["<INFO> 02-Jan-2024::15:04:05.123 a b: - First\n  more\n"];
+
+        assert_eq!(
+            first_message(&chunks, true),
+            ("First\n  more".to_string(), 1)
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let text = "\
+<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Starting up\r
+<ERROR> 02-Jan-2024::15:04:06.000 my-logger MainThread: - Boom\r
+  line 2\r
+<WARNING> 02-Jan-2024::15:04:07.000 my-logger MainThread: - No line feed\r";
+        let loglines = parse(text);
+
+        assert_eq!(
+            messages(&loglines),
+            ["Starting up", "Boom\n  line 2", "No line feed"]
+        );
+        assert_eq!(normal(&loglines[0]).thread, "MainThread");
+        assert_eq!(
+            normal(&loglines[1]).datetime,
+            Utc.with_ymd_and_hms(2024, 1, 2, 15, 4, 6).unwrap()
+        );
+    }
+
+    #[test]
+    fn crlf_in_dangling_lines() {
+        let loglines = parse("cut off\r\n  traceback\r\n");
+
+        let texts: Vec<&str> = loglines
+            .iter()
+            .map(|logline| match logline {
+                LogLine::Dangling(dangling) => dangling.text.as_str(),
+                LogLine::Normal(_) => panic!("Not dangling"),
+            })
+            .collect();
+        assert_eq!(texts, ["cut off", "  traceback"]);
+    }
+
+    /// Each item as "N message" for log messages and "D text" for dangling lines
+    fn items(text: &str) -> Vec<String> {
+        parse(text)
+            .iter()
+            .map(|logline| match logline {
+                LogLine::Normal(logline) => format!("N {}", logline.message),
+                LogLine::Dangling(dangling) => format!("D {}", dangling.text),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn multi_line_messages_are_grouped() {
+        let text = "\
+<INFO> 02-Jan-2024::15:04:05.123 a b: - One
+  two
+
+  three
+<ERROR> 02-Jan-2024::15:04:06.123 a b: - Boom
+Traceback (most recent call last):
+  File \"x.py\", line 1
+<INFO> 02-Jan-2024::15:04:07.123 a b: - Single
+";
+
+        assert_eq!(
+            items(text),
+            [
+                "N One\n  two\n\n  three",
+                "N Boom\nTraceback (most recent call last):\n  File \"x.py\", line 1",
+                "N Single",
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_before_the_first_message_are_dangling() {
+        let text = "\
+end of a traceback
+  cut off by rotation
+<INFO> 02-Jan-2024::15:04:05.123 a b: - One
+";
+
+        assert_eq!(
+            items(text),
+            ["D end of a traceback", "D   cut off by rotation", "N One"]
+        );
+    }
+
+    #[test]
+    fn input_without_log_messages() {
+        assert!(items("").is_empty());
+        assert_eq!(items("\n\n"), ["D ", "D "]);
+        assert_eq!(items("just\ntext"), ["D just", "D text"]);
+    }
+
+    #[test]
+    fn last_message_without_a_newline() {
+        assert_eq!(
+            items("<INFO> 02-Jan-2024::15:04:05.123 a b: - One\n  two\n<INFO> 02-Jan-2024::15:04:06.123 a b: - Last"),
+            ["N One\n  two", "N Last"]
+        );
+        assert_eq!(
+            items("<INFO> 02-Jan-2024::15:04:05.123 a b: - One\n  two"),
+            ["N One\n  two"]
+        );
+    }
+}
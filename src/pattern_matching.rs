@@ -1,23 +1,134 @@
-use glob::glob;
+use std::cmp::Reverse;
+use std::fmt::Display;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
 
-pub fn match_pattern(patterns: &Vec<String>) -> Result<Vec<String>, String> {
-    let nso_run = match std::env::var("NSO_RUN_DIR") {
-        Ok(x) => x,
-        Err(_) => return Err("Expected environment variable: NSO_RUN_DIR".to_string()),
-    };
+use chrono::{DateTime, Utc};
+use glob::{glob, Pattern};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum SortBy {
+    /// Shortest file name first
+    Name,
+    /// Most recently modified first
+    Mtime,
+    /// Largest file first
+    Size,
+}
 
-    let log_files = glob(&format!("{}/logs/ncs-python-vm-*", nso_run))
-        .unwrap()
-        .map(|x| x.unwrap())
-        .collect::<Vec<_>>();
+/// Which match is opened when several log files match
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Prefer {
+    /// The one with the shortest file name
+    Shortest,
+    /// The one with the longest file name
+    Longest,
+    /// The most recently modified one
+    Mtime,
+}
 
-    if log_files.is_empty() {
-        return Err(format!("Couldn't find any log files in {}/logs/", nso_run));
+/// A log file matched by `match_pattern`
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFile {
+    pub filename: String,
+    pub path: PathBuf,
+}
+
+/// A match as printed by "--print-matches --format json"
+#[derive(Serialize)]
+pub struct MatchReport<'a> {
+    filename: &'a str,
+    path: &'a Path,
+    /// Whether this is the file that would be opened
+    selected: bool,
+    size: Option<u64>,
+    mtime: Option<DateTime<Utc>>,
+}
+
+impl LogFile {
+    /// Describes the match, the size and mtime are left out if the file can't be stat-ed
+    pub fn report(&self, selected: bool) -> MatchReport<'_> {
+        let metadata = self.path.metadata().ok();
+
+        MatchReport {
+            filename: &self.filename,
+            path: &self.path,
+            selected,
+            size: metadata.as_ref().map(|metadata| metadata.len()),
+            mtime: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .map(DateTime::from),
+        }
     }
+}
+
+/// The glob used to find log files in the NSO logs directory
+pub const DEFAULT_GLOB: &str = "ncs-python-vm-*";
+
+/// The NSO logs directory, `log_dir` if given, otherwise "$NSO_RUN_DIR/logs"
+pub fn logs_dir(log_dir: Option<&Path>) -> Result<PathBuf, String> {
+    let (logs_dir, setting) = match log_dir {
+        Some(log_dir) => (log_dir.to_path_buf(), "--log-dir or NSO_LOG_DIR"),
+        None => std::env::var("NSO_RUN_DIR")
+            .map(|nso_run| (Path::new(&nso_run).join("logs"), "NSO_RUN_DIR"))
+            .map_err(|_| "Expected environment variable: NSO_RUN_DIR or NSO_LOG_DIR".to_string())?,
+    };
+
+    // Otherwise the glob would just come up empty, which looks like there are no matches
+    if !logs_dir.is_dir() {
+        return Err(format!(
+            "NSO logs directory not found at {}; is {} correct?",
+            logs_dir.display(),
+            setting
+        ));
+    }
+
+    Ok(logs_dir)
+}
+
+/// Finds the log files in `logs_dir` whose names contain every pattern
+///
+/// With `loose`, separators ("-", "_" and whitespace) are ignored in both the names and the
+/// patterns, so "l3 vpn" matches "l3vpn" and "cfs_l3vpn" matches "cfs-l3vpn".
+///
+/// With `first_only`, only the best match is returned, the shortest name (then alphabetically
+/// first). That's what would be opened by default, found without sorting every match.
+///
+/// Unreadable entries in the logs directory are skipped, with a warning unless `quiet` is set.
+///
+pub fn match_pattern(
+    patterns: &[String],
+    logs_dir: &Path,
+    file_glob: &str,
+    loose: bool,
+    first_only: bool,
+    quiet: bool,
+) -> Result<Vec<LogFile>, String> {
+    let logs_dir = logs_dir.to_string_lossy();
+
+    // The directory is taken literally, only the file glob may contain wildcards
+    let mut file_count = 0;
+    let log_files = glob(&format!("{}/{}", Pattern::escape(&logs_dir), file_glob))
+        .map_err(|err| format!("Invalid glob pattern: {}", err))?
+        .filter_map(|entry| readable(entry, quiet))
+        .inspect(|_| file_count += 1);
+
+    let normalize = |text: &str| -> String {
+        match loose {
+            true => text
+                .chars()
+                .filter(|c| !(*c == '-' || *c == '_' || c.is_whitespace()))
+                .collect(),
+            false => text.to_string(),
+        }
+    };
+    let patterns: Vec<String> = patterns.iter().map(|pattern| normalize(pattern)).collect();
 
     let matches_patterns = |filename: &String| -> bool {
-        for pattern in patterns {
-            if !filename.contains(pattern) {
+        let filename = normalize(filename);
+        for pattern in &patterns {
+            if !filename.contains(pattern.as_str()) {
                 return false;
             }
         }
@@ -25,16 +136,346 @@ pub fn match_pattern(patterns: &Vec<String>) -> Result<Vec<String>, String> {
         true
     };
 
-    let mut matches: Vec<String> = log_files
-        .iter()
-        .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
-        .filter(matches_patterns)
-        .collect();
+    let matching = log_files
+        .map(|path| LogFile {
+            filename: path.file_name().unwrap().to_str().unwrap().to_string(),
+            path,
+        })
+        .filter(|log_file| matches_patterns(&log_file.filename));
 
-    matches.sort_by(|a, b| match a.len().cmp(&b.len()) {
-        std::cmp::Ordering::Equal => a.cmp(b),
+    // Shortest first, then alphabetically
+    let by_name = |a: &LogFile, b: &LogFile| match a.filename.len().cmp(&b.filename.len()) {
+        std::cmp::Ordering::Equal => a.filename.cmp(&b.filename),
         x => x,
-    });
+    };
+
+    // Only the best match is kept, so there's nothing to collect and sort
+    let matches: Vec<LogFile> = match first_only {
+        true => matching.min_by(by_name).into_iter().collect(),
+        false => {
+            let mut matches: Vec<LogFile> = matching.collect();
+            matches.sort_by(by_name);
+            matches
+        }
+    };
+
+    if file_count == 0 {
+        return Err(format!("Couldn't find any log files in {}", logs_dir));
+    }
 
     Ok(matches)
 }
+
+/// Skips an entry that couldn't be read, with a warning unless `quiet` is set
+///
+/// One unreadable entry (permissions, broken symlinks) shouldn't stop the whole run.
+///
+fn readable<E: Display>(entry: Result<PathBuf, E>, quiet: bool) -> Option<PathBuf> {
+    match entry {
+        Ok(path) => Some(path),
+        Err(err) => {
+            if !quiet {
+                eprintln!("Warning: skipping unreadable file, {}", err);
+            }
+            None
+        }
+    }
+}
+
+/// Re-sorts a list of matches as returned by `match_pattern` for display
+pub fn sort_matches(matches: Vec<LogFile>, sort_by: SortBy) -> Result<Vec<LogFile>, String> {
+    // This is the order match_pattern already returns, no need to stat anything
+    if let SortBy::Name = sort_by {
+        return Ok(matches);
+    }
+
+    let mut matches = with_metadata(matches)?;
+
+    // Sorting is stable, so ties keep the shortest-name-first order
+    match sort_by {
+        SortBy::Name => {}
+        SortBy::Mtime => matches.sort_by_key(|(metadata, _)| Reverse(metadata.modified().ok())),
+        SortBy::Size => matches.sort_by_key(|(metadata, _)| Reverse(metadata.len())),
+    }
+
+    Ok(matches.into_iter().map(|(_, log_file)| log_file).collect())
+}
+
+/// Picks the preferred match out of the matches as returned by `match_pattern`
+///
+/// Ties are broken by the shortest-name-first order of the matches.
+///
+pub fn preferred_match(matches: &[LogFile], prefer: Prefer) -> Result<Option<LogFile>, String> {
+    let preferred = match prefer {
+        Prefer::Shortest => matches.first(),
+        Prefer::Longest => matches
+            .iter()
+            .rev()
+            .max_by_key(|log_file| log_file.filename.len()),
+        Prefer::Mtime => {
+            return Ok(with_metadata(matches.to_vec())?
+                .into_iter()
+                .rev()
+                .max_by_key(|(metadata, _)| metadata.modified().ok())
+                .map(|(_, log_file)| log_file))
+        }
+    };
+
+    Ok(preferred.cloned())
+}
+
+/// Pairs each matched log file with its metadata
+fn with_metadata(matches: Vec<LogFile>) -> Result<Vec<(Metadata, LogFile)>, String> {
+    matches
+        .into_iter()
+        .map(|log_file| {
+            let metadata = std::fs::metadata(&log_file.path)
+                .map_err(|err| format!("{}: {}", log_file.filename, err))?;
+
+            Ok((metadata, log_file))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// A logs directory with the given (empty) log files, inside a parent directory named `name`
+    fn logs_dir_with(name: &str, filenames: &[&str]) -> (TempDir, PathBuf) {
+        let parent = TempDir::new().unwrap();
+        let logs_dir = parent.path().join(name).join("logs");
+        std::fs::create_dir_all(&logs_dir).unwrap();
+        for filename in filenames {
+            File::create(logs_dir.join(filename)).unwrap();
+        }
+
+        (parent, logs_dir)
+    }
+
+    fn filenames(matches: &[LogFile]) -> Vec<&str> {
+        matches
+            .iter()
+            .map(|log_file| log_file.filename.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn glob_characters_in_the_directory_are_taken_literally() {
+        let (_parent, logs_dir) = logs_dir_with("nso[1]*", &["ncs-python-vm-l3vpn.log"]);
+        let matches = match_pattern(
+            &["l3vpn".to_string()],
+            &logs_dir,
+            DEFAULT_GLOB,
+            false,
+            false,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(filenames(&matches), ["ncs-python-vm-l3vpn.log"]);
+    }
+
+    #[test]
+    fn missing_logs_dir() {
+        let (parent, existing) = logs_dir_with("nso", &[]);
+        let missing = parent.path().join("typo/logs");
+
+        assert_eq!(logs_dir(Some(&existing)), Ok(existing.clone()));
+        assert_eq!(
+            logs_dir(Some(&missing)),
+            Err(format!(
+                "NSO logs directory not found at {}; is --log-dir or NSO_LOG_DIR correct?",
+                missing.display()
+            ))
+        );
+        // A file isn't a directory either
+        File::create(existing.join("ncs.log")).unwrap();
+        assert!(logs_dir(Some(&existing.join("ncs.log"))).is_err());
+    }
+
+    const LOGS: &[&str] = &[
+        "ncs-python-vm-cfs-l3vpn.log",
+        "ncs-python-vm-l3vpn.log",
+        "ncs-python-vm-rfs_l3vpn.log",
+        "ncs-python-vm-l3vpn-old.log",
+        "ncs-python-vm-other.log",
+    ];
+
+    fn matching(patterns: &[&str], loose: bool, first_only: bool) -> Vec<String> {
+        let (_parent, logs_dir) = logs_dir_with("nso", LOGS);
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        let matches =
+            match_pattern(&patterns, &logs_dir, DEFAULT_GLOB, loose, first_only, true).unwrap();
+
+        filenames(&matches).into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn matches_are_sorted_shortest_first() {
+        assert_eq!(
+            matching(&["l3vpn"], false, false),
+            [
+                "ncs-python-vm-l3vpn.log",
+                "ncs-python-vm-cfs-l3vpn.log",
+                "ncs-python-vm-l3vpn-old.log",
+                "ncs-python-vm-rfs_l3vpn.log",
+            ]
+        );
+        assert_eq!(
+            matching(&["l3vpn", "old"], false, false),
+            ["ncs-python-vm-l3vpn-old.log"]
+        );
+        assert!(matching(&["l3 vpn"], false, false).is_empty());
+    }
+
+    #[test]
+    fn loose_ignores_separators() {
+        assert_eq!(
+            matching(&["cfs_l3vpn"], true, false),
+            ["ncs-python-vm-cfs-l3vpn.log"]
+        );
+        assert_eq!(
+            matching(&["rfs-l3 vpn"], true, false),
+            ["ncs-python-vm-rfs_l3vpn.log"]
+        );
+        assert_eq!(
+            matching(&["l3vpnold"], true, false),
+            ["ncs-python-vm-l3vpn-old.log"]
+        );
+        assert!(matching(&["cfs_l3vpn"], false, false).is_empty());
+    }
+
+    #[test]
+    fn first_only_keeps_the_best_match() {
+        for loose in [false, true] {
+            assert_eq!(
+                matching(&["l3vpn"], loose, true),
+                ["ncs-python-vm-l3vpn.log"]
+            );
+            // Equally long names are ordered alphabetically
+            assert_eq!(
+                matching(&["fs"], loose, true),
+                ["ncs-python-vm-cfs-l3vpn.log"]
+            );
+            assert!(matching(&["nothing"], loose, true).is_empty());
+        }
+    }
+
+    #[test]
+    fn no_log_files_at_all() {
+        let (_parent, logs_dir) = logs_dir_with("nso", &["README"]);
+        let error = match_pattern(&[], &logs_dir, DEFAULT_GLOB, false, true, true).unwrap_err();
+
+        assert!(
+            error.starts_with("Couldn't find any log files in "),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn sort_and_prefer() {
+        let (_parent, logs_dir) = logs_dir_with("nso", &[]);
+        // (name, size, mtime in seconds)
+        let files = [
+            ("a-big.log", 300, 100),
+            ("b-newest-long.log", 100, 300),
+            ("c.log", 200, 200),
+        ];
+        for (name, size, mtime) in files {
+            let file = File::create(logs_dir.join(name)).unwrap();
+            file.set_len(size).unwrap();
+            file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(mtime))
+                .unwrap();
+        }
+        let matches = match_pattern(&[], &logs_dir, "*.log", false, false, true).unwrap();
+        let sorted =
+            |sort_by| filenames(&sort_matches(matches.clone(), sort_by).unwrap()).join(" ");
+        let preferred = |prefer| {
+            preferred_match(&matches, prefer)
+                .unwrap()
+                .map(|log_file| log_file.filename)
+        };
+
+        assert_eq!(sorted(SortBy::Name), "c.log a-big.log b-newest-long.log");
+        assert_eq!(sorted(SortBy::Mtime), "b-newest-long.log c.log a-big.log");
+        assert_eq!(sorted(SortBy::Size), "a-big.log c.log b-newest-long.log");
+
+        assert_eq!(preferred(Prefer::Shortest).as_deref(), Some("c.log"));
+        assert_eq!(
+            preferred(Prefer::Longest).as_deref(),
+            Some("b-newest-long.log")
+        );
+        assert_eq!(
+            preferred(Prefer::Mtime).as_deref(),
+            Some("b-newest-long.log")
+        );
+        assert_eq!(preferred_match(&[], Prefer::Mtime), Ok(None));
+    }
+
+    #[test]
+    fn ties_keep_the_name_order() {
+        let (_parent, logs_dir) = logs_dir_with("nso", &["bb.log", "aa.log", "c.log"]);
+        let matches = match_pattern(&[], &logs_dir, "*.log", false, false, true).unwrap();
+
+        // The files are all empty
+        assert_eq!(
+            filenames(&sort_matches(matches.clone(), SortBy::Size).unwrap()),
+            ["c.log", "aa.log", "bb.log"]
+        );
+        assert_eq!(
+            preferred_match(&matches, Prefer::Longest)
+                .unwrap()
+                .map(|log_file| log_file.filename),
+            Some("aa.log".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_glob() {
+        let (_parent, logs_dir) = logs_dir_with("nso", &["ncs-python-vm-l3vpn.log"]);
+        let error = match_pattern(&[], &logs_dir, "ncs-[", false, false, true).unwrap_err();
+
+        assert!(error.starts_with("Invalid glob pattern: "), "{}", error);
+    }
+
+    #[test]
+    fn unreadable_entries_are_skipped() {
+        assert_eq!(
+            readable::<&str>(Ok(PathBuf::from("a.log")), true),
+            Some("a.log".into())
+        );
+        assert_eq!(readable(Err("Permission denied"), true), None);
+    }
+
+    #[test]
+    fn unreadable_directories_are_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (_parent, logs_dir) = logs_dir_with("nso", &[]);
+        for dir in ["open", "locked"] {
+            std::fs::create_dir(logs_dir.join(dir)).unwrap();
+            File::create(logs_dir.join(dir).join("ncs-python-vm-l3vpn.log")).unwrap();
+        }
+        let locked = logs_dir.join("locked");
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+
+        let matches = match_pattern(&[], &logs_dir, "*/ncs-*", false, false, true);
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        // Permissions don't apply to root, then both are found
+        let matches = matches.unwrap();
+        assert!(matches
+            .iter()
+            .any(|log_file| log_file.path.starts_with(logs_dir.join("open"))));
+        // SAFETY: geteuid has no memory safety requirements
+        if unsafe { libc::geteuid() } != 0 {
+            assert_eq!(matches.len(), 1);
+        }
+    }
+}
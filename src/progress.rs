@@ -0,0 +1,58 @@
+use std::fs::File;
+use std::io::Seek;
+
+/// How many updates to skip between each time the file position is checked
+const CHECK_INTERVAL: usize = 1000;
+
+/// Prints how far into a file we've read to STDERR
+///
+/// The position is read from a duplicate of the file handle, which shares its offset with the
+/// original, so we don't have to count bytes as they are read.
+///
+pub struct Progress {
+    file: File,
+    size: u64,
+    updates: usize,
+    last_percent: Option<u64>,
+}
+
+impl Progress {
+    pub fn new(file: &File) -> Result<Self, String> {
+        Ok(Self {
+            file: file.try_clone().map_err(|err| err.to_string())?,
+            size: file.metadata().map_err(|err| err.to_string())?.len(),
+            updates: 0,
+            last_percent: None,
+        })
+    }
+
+    pub fn update(&mut self) {
+        self.updates += 1;
+        if !self.updates.is_multiple_of(CHECK_INTERVAL) {
+            return;
+        }
+
+        let Ok(position) = self.file.stream_position() else {
+            return;
+        };
+
+        let percent = (position * 100)
+            .checked_div(self.size)
+            .unwrap_or(100)
+            .min(100);
+
+        if self.last_percent != Some(percent) {
+            eprint!("\rProgress: {}%", percent);
+            self.last_percent = Some(percent);
+        }
+    }
+
+    pub fn finish(&self) {
+        match self.last_percent {
+            // Never printed in the first place, the file was tiny
+            None => {}
+            Some(100) => eprintln!(),
+            Some(_) => eprintln!("\rProgress: 100%"),
+        }
+    }
+}
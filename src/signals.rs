@@ -0,0 +1,75 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+/// The signal that asked us to stop, or 0 if there hasn't been one
+///
+/// This is set from the signal handler itself, so it's already set when an interrupted system
+/// call returns in the main thread.
+///
+static RECEIVED: LazyLock<Arc<AtomicUsize>> = LazyLock::new(|| Arc::new(AtomicUsize::new(0)));
+
+/// Child processes (like "tail") to terminate when we're asked to stop
+static CHILDREN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Handles SIGINT and SIGTERM so we can shut down cleanly
+///
+/// The first signal terminates the registered child processes and asks the main loop to stop,
+/// so the output is flushed and the pager is waited for before exiting. The main loop may be
+/// blocked waiting for input, so a second signal exits immediately.
+///
+/// This is Unix-specific, like the rest of the program.
+///
+pub fn install() -> Result<(), String> {
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_usize(signal, Arc::clone(&RECEIVED), signal as usize)
+            .map_err(|err| err.to_string())?;
+    }
+
+    let mut signals = Signals::new([SIGINT, SIGTERM]).map_err(|err| err.to_string())?;
+
+    std::thread::spawn(move || {
+        let mut stopping = false;
+
+        for signal in signals.forever() {
+            if stopping {
+                std::process::exit(128 + signal);
+            }
+            stopping = true;
+
+            for pid in CHILDREN.lock().unwrap().drain(..) {
+                // SAFETY: kill has no memory safety requirements
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGTERM);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Registers a child process to be terminated when we're asked to stop
+pub fn register_child(pid: u32) {
+    CHILDREN.lock().unwrap().push(pid);
+}
+
+/// Forgets a child process before it's reaped, after which its PID may be reused
+pub fn unregister_child(pid: u32) {
+    CHILDREN.lock().unwrap().retain(|&child| child != pid);
+}
+
+/// Whether we've been asked to stop
+pub fn interrupted() -> bool {
+    RECEIVED.load(Ordering::SeqCst) != 0
+}
+
+/// The conventional exit code after being stopped by a signal, 130 for SIGINT
+pub fn exit_code() -> Option<i32> {
+    match RECEIVED.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(128 + signal as i32),
+    }
+}
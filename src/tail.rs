@@ -1,11 +1,65 @@
-use std::process::{ChildStdout, Command, Stdio};
+use std::io::Read;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::Duration;
 
-pub fn tail(filepath: &str) -> Result<ChildStdout, String> {
-    let child = Command::new("tail")
-        .args(["-f", "-n", "100", filepath])
+/// A running "tail -f", reading from it reads the followed file
+///
+/// The child process is killed and reaped when this is dropped, so it doesn't linger (or turn
+/// into a zombie) once we're done following.
+///
+pub struct Tail {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for Tail {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl AsRawFd for Tail {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stdout.as_raw_fd()
+    }
+}
+
+impl Drop for Tail {
+    fn drop(&mut self) {
+        // Unregistered before it's killed, so a signal in between can't hit a reused PID
+        crate::signals::unregister_child(self.child.id());
+
+        // Fails if tail already exited, which is fine, it still needs to be reaped
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Follows a file using "tail -f"
+///
+/// There's no native follow loop, so truncation is handled by tail itself: when the file
+/// shrinks (as with in-place log rotation) it prints "file truncated" to STDERR and carries on
+/// reading from the start of the file.
+///
+/// `lines` is passed on to "tail -n", so "100" starts with the last 100 lines and "+1" starts
+/// from the beginning of the file. `poll_interval` is passed on to "tail -s", which only uses it
+/// when it can't be notified of changes to the file.
+///
+pub fn tail(filepath: &Path, lines: &str, poll_interval: Duration) -> Result<Tail, String> {
+    let mut child = Command::new("tail")
+        .args(["-f", "-n", lines])
+        .arg("-s")
+        .arg(poll_interval.as_secs_f64().to_string())
+        .arg(filepath)
         .stdout(Stdio::piped())
         .spawn()
         .map_err(|err| err.to_string())?;
 
-    Ok(child.stdout.unwrap())
+    crate::signals::register_child(child.id());
+
+    let stdout = child.stdout.take().unwrap();
+
+    Ok(Tail { child, stdout })
 }
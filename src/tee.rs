@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::ansi::strip_ansi;
+
+/// Writes everything to both `inner` and a file, like "tee"
+///
+/// Colors are stripped from the file's copy unless asked to keep them. Output is passed on to
+/// the file a line at a time, so escape sequences are never split when stripping them.
+///
+pub struct Tee<W: Write> {
+    inner: W,
+    file: BufWriter<File>,
+    keep_colors: bool,
+    line: Vec<u8>,
+}
+
+impl<W: Write> Tee<W> {
+    pub fn new(inner: W, file: File) -> Self {
+        Self {
+            inner,
+            file: BufWriter::new(file),
+            keep_colors: false,
+            line: Vec::new(),
+        }
+    }
+
+    pub fn keep_colors(mut self, keep_colors: bool) -> Self {
+        self.keep_colors = keep_colors;
+        self
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.keep_colors {
+            return self.file.write_all(line);
+        }
+
+        let line = String::from_utf8_lossy(line);
+        self.file.write_all(strip_ansi(&line).as_bytes())
+    }
+}
+
+impl<W: Write> Write for Tee<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.line.extend_from_slice(&buf[..written]);
+
+        while let Some(end) = self.line.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=end).collect();
+            self.write_line(&line)?;
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        self.write_line(&line)?;
+        self.file.flush()?;
+        self.inner.flush()
+    }
+}
+
+/// Opens the file to tee output to, truncating it if it exists
+pub fn open_tee_file(path: &Path) -> Result<File, String> {
+    File::create(path).map_err(|err| format!("Failed to open {}: {}", path.display(), err))
+}
@@ -0,0 +1,79 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+const LOG: &str = "\
+<INFO> 02-Jan-2024::15:04:05.123 my-logger MainThread: - Starting up
+<DEBUG> 02-Jan-2024::15:04:06.456 my-logger Thread-1: - Something happened
+Traceback line 1
+  line 2
+<ERROR> 02-Jan-2024::15:05:00.000 other ThreadPool-2: - Boom
+<WARNING> 02-Jan-2024::16:00:00.000 my-logger MainThread: - Careful
+";
+
+/// Runs "--check" on STDIN, writing `input` in pieces with a pause after each
+fn check(pieces: &[&str], args: &[&str]) -> (i32, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nso-log-reader"))
+        .arg("--check")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    for piece in pieces {
+        stdin.write_all(piece.as_bytes()).unwrap();
+        stdin.flush().unwrap();
+        sleep(Duration::from_millis(300));
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code().unwrap(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn passes_on_a_delayed_pipe() {
+    let (first, rest) = LOG.split_at(LOG.find('\n').unwrap() + 1);
+    let (code, stdout) = check(&[first, rest], &[]);
+
+    assert_eq!(code, 0, "{}", stdout);
+    assert!(stdout.starts_with("OK: (STDIN), 6 lines, 4 log messages, 0 dangling lines"));
+}
+
+#[test]
+fn passes_on_a_pause_in_the_middle_of_a_line() {
+    let (code, stdout) = check(&[&LOG[..20], &LOG[20..]], &[]);
+
+    assert_eq!(code, 0, "{}", stdout);
+    assert!(stdout.starts_with("OK: (STDIN), 6 lines, 4 log messages"));
+}
+
+#[test]
+fn fails_when_too_much_is_dangling() {
+    let (code, stdout) = check(&["not\nan\nNSO\nlog\n"], &[]);
+
+    assert_eq!(code, 3, "{}", stdout);
+    assert!(stdout.starts_with("FAIL: (STDIN), 4 lines, 0 log messages, 4 dangling lines"));
+}
+
+#[test]
+fn only_checks_the_last_lines() {
+    let (code, stdout) = check(&[LOG], &["--check-lines", "2"]);
+
+    assert_eq!(code, 0, "{}", stdout);
+    assert!(stdout.starts_with("OK: (STDIN), 2 lines, 2 log messages"));
+}
+
+#[test]
+fn rejects_zero_lines() {
+    // Nothing is written, it exits before reading
+    let (code, _) = check(&[], &["--check-lines", "0"]);
+
+    assert_eq!(code, 2);
+}
@@ -0,0 +1,244 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+use tempfile::TempDir;
+
+/// A log file with the given contents, in a directory that's removed when it's dropped
+fn log_file(contents: &str) -> (TempDir, PathBuf) {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("ncs-python-vm-test.log");
+    std::fs::write(&path, contents).unwrap();
+
+    (dir, path)
+}
+
+/// The messages in "--format json" output
+fn messages(output: &Output) -> Vec<String> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            let logline: serde_json::Value = serde_json::from_str(line).unwrap();
+            logline["message"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+fn run(args: &[&str], nso_run_dir: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_nso-log-reader"))
+        .args(args)
+        .env("NSO_RUN_DIR", nso_run_dir)
+        .env_remove("NSO_LOG_DIR")
+        .env_remove("NSO_LOG_READER_DEFAULT_LOG")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn run_dir_with_glob_characters() {
+    let parent = TempDir::new().unwrap();
+    let nso_run_dir = parent.path().join("nso[1]");
+    std::fs::create_dir_all(nso_run_dir.join("logs")).unwrap();
+    File::create(nso_run_dir.join("logs/ncs-python-vm-l3vpn.log")).unwrap();
+
+    let output = run(&["--print-path", "l3vpn"], &nso_run_dir);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        nso_run_dir
+            .join("logs/ncs-python-vm-l3vpn.log")
+            .to_str()
+            .unwrap()
+    );
+}
+
+#[test]
+fn missing_run_dir() {
+    let parent = TempDir::new().unwrap();
+    let nso_run_dir = parent.path().join("nso");
+
+    let output = run(&["l3vpn"], &nso_run_dir);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!(
+            "NSO logs directory not found at {}; is NSO_RUN_DIR correct?",
+            nso_run_dir.join("logs").display()
+        )),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn strip_colors() {
+    let (dir, path) = log_file(
+        "<INFO> 02-Jan-2024::15:04:05.123 a b: - \x1b[31mred\x1b[0m and \x1b[1mbold\x1b[22m\n",
+    );
+    let path = path.to_str().unwrap();
+
+    let stripped = run(
+        &["-F", path, "-c", "--format", "json", "--strip-colors"],
+        dir.path(),
+    );
+    let kept = run(&["-F", path, "-c", "--format", "json"], dir.path());
+
+    assert_eq!(messages(&stripped), ["red and bold"]);
+    assert_eq!(
+        messages(&kept),
+        ["\x1b[31mred\x1b[0m and \x1b[1mbold\x1b[22m"]
+    );
+}
+
+#[test]
+fn around_includes_both_ends_of_the_window() {
+    let (dir, path) = log_file(
+        "\
+<INFO> 02-Jan-2024::14:59:59.999 a b: - Just before
+<INFO> 02-Jan-2024::15:00:00.000 a b: - Start
+<INFO> 02-Jan-2024::15:05:00.000 a b: - Around
+<INFO> 02-Jan-2024::15:10:00.000 a b: - End
+<INFO> 02-Jan-2024::15:10:00.001 a b: - Just after
+",
+    );
+    let args = [
+        "-F",
+        path.to_str().unwrap(),
+        "-c",
+        "--utc",
+        "--format",
+        "json",
+        "--around",
+        "2024-01-02 15:05:00",
+        "--window",
+        "5m",
+    ];
+    let output = run(&args, dir.path());
+
+    assert_eq!(messages(&output), ["Start", "Around", "End"]);
+}
+
+#[test]
+fn no_carriage_returns_in_the_output() {
+    let (dir, path) = log_file(
+        "<INFO> 02-Jan-2024::15:04:05.123 a b: - One\r\n  two\r\n<INFO> 02-Jan-2024::15:04:06.000 a b: - Three\r",
+    );
+    let path = path.to_str().unwrap();
+
+    for args in [
+        &["-F", path, "-c"][..],
+        &["-F", path, "-c", "--passthrough"],
+    ] {
+        let output = run(args, dir.path());
+
+        assert_eq!(output.status.code(), Some(0));
+        assert!(!output.stdout.contains(&b'\r'), "{:?}", args);
+    }
+}
+
+#[test]
+fn exit_status_of_filters() {
+    let (dir, path) =
+        log_file("orphan NEEDLE line\n<INFO> 02-Jan-2024::15:04:05.123 a b: - Message HAY\n");
+    let path = path.to_str().unwrap();
+
+    // A dangling line that matches counts as much as a log message
+    for (pattern, shown, code) in [
+        ("NEEDLE", Some("orphan NEEDLE line"), 0),
+        ("HAY", Some("Message HAY"), 0),
+        ("NOTHING", None, 1),
+    ] {
+        let output = run(&["-F", path, "-c", "--grep", pattern], dir.path());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+
+        assert_eq!(output.status.code(), Some(code), "{}", pattern);
+        match shown {
+            Some(shown) => {
+                assert_eq!(stdout.lines().count(), 1, "{}", stdout);
+                assert!(stdout.contains(shown), "{}", stdout);
+            }
+            None => assert!(stdout.is_empty(), "{}", stdout),
+        }
+    }
+}
+
+#[test]
+fn max_lines_counts_log_messages() {
+    let (dir, path) = log_file(
+        "\
+orphan
+<INFO> 02-Jan-2024::15:04:05.123 a b: - One
+<INFO> 02-Jan-2024::15:04:06.123 a b: - Two
+  more of two
+<INFO> 02-Jan-2024::15:04:07.123 a b: - Three
+",
+    );
+    let output = run(
+        &[
+            "-F",
+            path.to_str().unwrap(),
+            "-c",
+            "--format",
+            "json",
+            "--max-lines",
+            "2",
+        ],
+        dir.path(),
+    );
+    let shown: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| {
+            let logline: serde_json::Value = serde_json::from_str(line).unwrap();
+            // Dangling lines only have their text
+            logline
+                .get("message")
+                .unwrap_or(&logline["text"])
+                .to_string()
+        })
+        .collect();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        shown,
+        [r#""orphan""#, r#""One""#, r#""Two\n  more of two""#]
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "(stopped at 2 log messages; use --max-lines 0 for unlimited)\n"
+    );
+}
+
+#[test]
+fn max_lines_stops_without_reading_further() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nso-log-reader"))
+        .args(["-F", "-", "--max-lines", "2", "--quiet"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // STDIN is kept open, so nothing but the cap can end this
+    let mut stdin = child.stdin.take().unwrap();
+    std::io::Write::write_all(
+        &mut stdin,
+        b"<INFO> 02-Jan-2024::15:04:05.123 a b: - One\n<INFO> 02-Jan-2024::15:04:06.123 a b: - Two\n",
+    )
+    .unwrap();
+
+    let started = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "still waiting for a third log message"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 2);
+}
@@ -0,0 +1,140 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+use tempfile::TempDir;
+
+/// Starts following `path` from its end, with one JSON object per log message
+fn follow(path: &Path) -> Child {
+    let child = Command::new(env!("CARGO_BIN_EXE_nso-log-reader"))
+        .args([
+            "-F",
+            path.to_str().unwrap(),
+            "-f",
+            "-n",
+            "0",
+            "--format",
+            "json",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    sleep(Duration::from_millis(500));
+
+    child
+}
+
+/// Stops following with SIGTERM, returning the output and the log messages in it
+fn stop(child: Child) -> (Output, Vec<serde_json::Value>) {
+    // SAFETY: kill has no memory safety requirements
+    unsafe {
+        libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+    }
+    let output = child.wait_with_output().unwrap();
+    let messages = String::from_utf8(output.stdout.clone())
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    (output, messages)
+}
+
+fn append(path: &Path, text: &str) {
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+    file.flush().unwrap();
+    sleep(Duration::from_millis(500));
+}
+
+#[test]
+fn follow_from_now_on() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("ncs-python-vm-test.log");
+    std::fs::write(
+        &path,
+        "<INFO> 02-Jan-2024::15:04:05.123 a b: - History\n  of the past\n",
+    )
+    .unwrap();
+
+    let child = follow(&path);
+    // The rest of the last message, then a new one
+    append(
+        &path,
+        "  still the past\n<WARNING> 02-Jan-2024::15:04:06.000 a b: - New\n",
+    );
+    let (output, messages) = stop(child);
+
+    assert_eq!(output.status.code(), Some(143));
+    assert_eq!(messages.len(), 1, "{:?}", messages);
+    assert_eq!(messages[0]["severity"], "WARNING");
+    assert_eq!(messages[0]["message"], "New");
+}
+
+#[test]
+fn follow_across_truncation() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("ncs-python-vm-test.log");
+    std::fs::write(
+        &path,
+        "<INFO> 02-Jan-2024::15:04:05.123 a b: - History from before the rotation\n",
+    )
+    .unwrap();
+
+    let child = follow(&path);
+    append(&path, "<INFO> 02-Jan-2024::15:04:06.000 a b: - Before\n");
+    // Rotated in place, like logrotate's "copytruncate"
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(0)
+        .unwrap();
+    sleep(Duration::from_millis(500));
+    append(&path, "<WARNING> 02-Jan-2024::15:05:00.000 a b: - After\n");
+    let (output, messages) = stop(child);
+
+    let messages: Vec<&str> = messages
+        .iter()
+        .map(|message| message["message"].as_str().unwrap())
+        .collect();
+    assert_eq!(output.status.code(), Some(143));
+    assert_eq!(messages, ["Before", "After"]);
+}
+
+/// The PIDs of the child processes of `pid`
+fn children(pid: u32) -> Vec<u32> {
+    std::fs::read_dir(format!("/proc/{}/task", pid))
+        .unwrap()
+        .map(|task| std::fs::read_to_string(task.unwrap().path().join("children")).unwrap())
+        .collect::<String>()
+        .split_whitespace()
+        .map(|child| child.parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn tail_is_stopped_with_us() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("ncs-python-vm-test.log");
+    std::fs::write(&path, "").unwrap();
+
+    let child = follow(&path);
+    let tails = children(child.id());
+    assert_eq!(tails.len(), 1, "{:?}", tails);
+    let tail = Path::new("/proc").join(tails[0].to_string());
+    assert_eq!(
+        std::fs::read_to_string(tail.join("comm"))
+            .unwrap()
+            .trim_end(),
+        "tail"
+    );
+    let (output, _) = stop(child);
+
+    assert_eq!(output.status.code(), Some(143));
+    assert!(!tail.exists(), "tail is still running");
+}